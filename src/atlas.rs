@@ -0,0 +1,161 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::input::Sprite;
+use crate::packer::{is_rotated, Packer};
+use crate::spatial_tree::SpatialTree;
+
+/// Location of a single sprite inside one of the atlas pages.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpriteMetadata {
+    pub name: String,
+    #[serde(default)]
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// The sprite is stored rotated 90 degrees clockwise, so `width` and `height` are swapped
+    /// relative to the source image.
+    #[serde(default)]
+    pub rotated: bool,
+    /// Transparent margins were cut off the source image before packing.
+    #[serde(default)]
+    pub trimmed: bool,
+    /// Position of the packed pixels inside the untrimmed source image.
+    #[serde(default)]
+    pub offset_x: u32,
+    #[serde(default)]
+    pub offset_y: u32,
+    /// Size of the untrimmed source image.
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Name of the sprite this one is a pixel-identical duplicate of. Both share the same
+    /// rectangle in the atlas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+}
+
+/// A single atlas image.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageMetadata {
+    pub image: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hidpi_image: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// The packing tree behind the page, holding sprite names, saved so a later run can add
+    /// sprites without moving the existing ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<SpatialTree<String>>,
+}
+
+/// Sidecar description of a packed atlas, written next to the page images.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct AtlasMetadata {
+    pub pages: Vec<PageMetadata>,
+    pub sprites: Vec<SpriteMetadata>,
+    /// The page images store color premultiplied by alpha.
+    #[serde(default)]
+    pub premultiplied_alpha: bool,
+    /// [`input_hash`](crate::input::input_hash) of the build that wrote this atlas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_hash: Option<String>,
+}
+
+impl SpriteMetadata {
+    /// Width of the packed pixels in the orientation of the source image.
+    pub fn trimmed_width(&self) -> u32 {
+        if self.rotated { self.height } else { self.width }
+    }
+
+    /// Height of the packed pixels in the orientation of the source image.
+    pub fn trimmed_height(&self) -> u32 {
+        if self.rotated { self.width } else { self.height }
+    }
+}
+
+impl PageMetadata {
+    /// The page image name without its extension, used to name per-page outputs.
+    pub fn stem(&self) -> &str {
+        Path::new(&self.image).file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(&self.image)
+    }
+}
+
+impl AtlasMetadata {
+    pub fn new() -> AtlasMetadata {
+        AtlasMetadata::default()
+    }
+
+    /// Appends a page holding every sprite placed by `packer`, followed by an entry for each
+    /// of its aliases. `name` turns a source path into a sprite name.
+    pub fn add_page<F>(&mut self, packer: &dyn Packer<Sprite>, image: &str, name: F)
+        where F: Fn(&Path) -> String
+    {
+        let page = self.pages.len();
+        let size = packer.size();
+        self.pages.push(PageMetadata {
+            image: image.to_string(),
+            hidpi_image: None,
+            width: size.width,
+            height: size.height,
+            layout: None,
+        });
+        let placements = packer.placements();
+        let mut aliases = Vec::new();
+        for (sprite, region) in &placements {
+            let metadata = SpriteMetadata {
+                name: name(&sprite.path),
+                page,
+                x: region.left,
+                y: region.top,
+                width: region.width,
+                height: region.height,
+                rotated: is_rotated(region, sprite.image.width()),
+                trimmed: sprite.is_trimmed(),
+                offset_x: sprite.offset.0,
+                offset_y: sprite.offset.1,
+                source_width: sprite.source_size.width,
+                source_height: sprite.source_size.height,
+                alias_of: None,
+            };
+            aliases.extend(sprite.aliases.iter()
+                .map(|alias| SpriteMetadata {
+                    name: name(alias),
+                    alias_of: Some(metadata.name.clone()),
+                    ..metadata.clone()
+                }));
+            self.sprites.push(metadata);
+        }
+        self.sprites.extend(aliases);
+    }
+
+    /// Metadata for a single page on its own, with its sprites renumbered to page 0.
+    pub fn page(&self, page: usize) -> AtlasMetadata {
+        AtlasMetadata {
+            pages: vec![self.pages[page].clone()],
+            sprites: self.sprites.iter()
+                .filter(|sprite| sprite.page == page)
+                .map(|sprite| SpriteMetadata { page: 0, ..sprite.clone() })
+                .collect(),
+            premultiplied_alpha: self.premultiplied_alpha,
+            input_hash: self.input_hash.clone(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<AtlasMetadata> {
+        let file = fs::File::open(path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(file))?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+//! CSS sprite classes and an SCSS map of sprite positions.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use super::strip_extension;
+use crate::atlas::{AtlasMetadata, SpriteMetadata};
+
+/// Builds a CSS class name from the configured prefix and a sprite name, replacing every
+/// character that isn't valid in an identifier with `-`.
+pub fn class_name(prefix: &str, name: &str) -> String {
+    let mut class = prefix.to_string();
+    for c in strip_extension(name).chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            class.push(c);
+        } else {
+            class.push('-');
+        }
+    }
+    if class.starts_with(|c: char| c.is_ascii_digit()) {
+        class.insert(0, '_');
+    }
+    class
+}
+
+/// Assigns a unique class name to every sprite, appending a counter when two names map to the
+/// same class. Sprites must already be in a stable order for the result to be stable.
+fn unique_class_names(prefix: &str, sprites: &[&SpriteMetadata]) -> Vec<String> {
+    let mut used = HashSet::new();
+    sprites.iter()
+        .map(|sprite| {
+            let base = class_name(prefix, &sprite.name);
+            let mut class = base.clone();
+            let mut counter = 2;
+            while !used.insert(class.clone()) {
+                class = format!("{}-{}", base, counter);
+                counter += 1;
+            }
+            class
+        })
+        .collect()
+}
+
+/// Quotes `value` as a CSS string, which SCSS reads the same way. Quotes and backslashes are
+/// escaped with a backslash and control characters as hexadecimal code points.
+fn css_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            },
+            c if c.is_control() => quoted.push_str(&format!("\\{:x} ", u32::from(c))),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn pixels(value: u32) -> String {
+    if value == 0 {
+        "0".to_string()
+    } else {
+        format!("{}px", value)
+    }
+}
+
+fn offset(value: u32) -> String {
+    if value == 0 {
+        "0".to_string()
+    } else {
+        format!("-{}px", value)
+    }
+}
+
+fn sorted_sprites(metadata: &AtlasMetadata) -> Vec<&SpriteMetadata> {
+    let mut sprites = metadata.sprites.iter().collect::<Vec<_>>();
+    sprites.sort_by(|a, b| a.name.cmp(&b.name));
+    sprites
+}
+
+const HIDPI_MEDIA_QUERY: &str = "@media (-webkit-min-device-pixel-ratio: 2), (min-resolution: 192dpi)";
+
+fn unrotated_sprites(metadata: &AtlasMetadata) -> io::Result<Vec<&SpriteMetadata>> {
+    match metadata.sprites.iter().find(|sprite| sprite.rotated) {
+        Some(sprite) => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{} is rotated, which CSS backgrounds can't show", sprite.name))),
+        None => Ok(sorted_sprites(metadata)),
+    }
+}
+
+pub fn write_css<W: Write>(metadata: &AtlasMetadata, prefix: &str, mut writer: W) -> io::Result<()> {
+    let sprites = unrotated_sprites(metadata)?;
+    let classes = unique_class_names(prefix, &sprites);
+    for (sprite, class) in sprites.iter().zip(&classes) {
+        writeln!(writer, ".{} {{", class)?;
+        writeln!(writer, "    background-image: url({});", css_string(&metadata.pages[sprite.page].image))?;
+        writeln!(writer, "    background-position: {} {};", offset(sprite.x), offset(sprite.y))?;
+        writeln!(writer, "    width: {};", pixels(sprite.width))?;
+        writeln!(writer, "    height: {};", pixels(sprite.height))?;
+        if sprite.trimmed {
+            // Margins pad the element back out to the untrimmed frame.
+            writeln!(writer, "    margin: {} {} {} {};", pixels(sprite.offset_y),
+                pixels(sprite.source_width - sprite.offset_x - sprite.trimmed_width()),
+                pixels(sprite.source_height - sprite.offset_y - sprite.trimmed_height()),
+                pixels(sprite.offset_x))?;
+        }
+        writeln!(writer, "}}")?;
+        writeln!(writer)?;
+    }
+
+    for (index, page) in metadata.pages.iter().enumerate() {
+        if let Some(hidpi_image) = &page.hidpi_image {
+            writeln!(writer, "{} {{", HIDPI_MEDIA_QUERY)?;
+            let selectors = sprites.iter()
+                .zip(&classes)
+                .filter(|(sprite, _)| sprite.page == index)
+                .map(|(_, class)| format!("    .{}", class))
+                .collect::<Vec<_>>();
+            writeln!(writer, "{} {{", selectors.join(",\n"))?;
+            writeln!(writer, "        background-image: url({});", css_string(hidpi_image))?;
+            writeln!(writer, "        background-size: {} {};", pixels(page.width), pixels(page.height))?;
+            writeln!(writer, "    }}")?;
+            writeln!(writer, "}}")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_scss<W: Write>(metadata: &AtlasMetadata, prefix: &str, mut writer: W) -> io::Result<()> {
+    let sprites = unrotated_sprites(metadata)?;
+    let has_hidpi = metadata.pages.iter().any(|page| page.hidpi_image.is_some());
+
+    writeln!(writer, "$sprites: (")?;
+    for (sprite, class) in sprites.iter().zip(unique_class_names(prefix, &sprites)) {
+        let page = &metadata.pages[sprite.page];
+        write!(writer, "    {}: (image: {}, ", css_string(&class),
+            css_string(&page.image))?;
+        if let Some(hidpi_image) = &page.hidpi_image {
+            write!(writer, "image-2x: {}, ", css_string(hidpi_image))?;
+        }
+        writeln!(writer, "atlas-width: {}, atlas-height: {}, x: {}, y: {}, width: {}, height: {}, \
+            offset-x: {}, offset-y: {}, source-width: {}, source-height: {}),",
+            pixels(page.width), pixels(page.height), offset(sprite.x), offset(sprite.y),
+            pixels(sprite.width), pixels(sprite.height), pixels(sprite.offset_x),
+            pixels(sprite.offset_y), pixels(sprite.source_width), pixels(sprite.source_height))?;
+    }
+    writeln!(writer, ");")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "@mixin sprite($name) {{")?;
+    writeln!(writer, "    $sprite: map-get($sprites, $name);")?;
+    writeln!(writer, "    background-image: url(map-get($sprite, image));")?;
+    writeln!(writer, "    background-position: map-get($sprite, x) map-get($sprite, y);")?;
+    writeln!(writer, "    width: map-get($sprite, width);")?;
+    writeln!(writer, "    height: map-get($sprite, height);")?;
+    if has_hidpi {
+        writeln!(writer)?;
+        writeln!(writer, "    @if map-has-key($sprite, image-2x) {{")?;
+        writeln!(writer, "        {} {{", HIDPI_MEDIA_QUERY)?;
+        writeln!(writer, "            background-image: url(map-get($sprite, image-2x));")?;
+        writeln!(writer, "            background-size: map-get($sprite, atlas-width) map-get($sprite, atlas-height);")?;
+        writeln!(writer, "        }}")?;
+        writeln!(writer, "    }}")?;
+    }
+    writeln!(writer, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn css_string_escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(css_string("sprites.png"), r#""sprites.png""#);
+        assert_eq!(css_string(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(css_string("a\nb\u{7f}"), "\"a\\a b\\7f \"");
+        assert_eq!(css_string("é 'x'"), "\"é 'x'\"");
+    }
+
+    #[test]
+    fn colliding_class_names_get_a_counter() {
+        let sprites = ["a/b.png", "a-b.png", "a-b-2.png", "a b.png"].iter()
+            .map(|name| SpriteMetadata {
+                name: name.to_string(),
+                page: 0,
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+                rotated: false,
+                trimmed: false,
+                offset_x: 0,
+                offset_y: 0,
+                source_width: 1,
+                source_height: 1,
+                alias_of: None,
+            })
+            .collect::<Vec<_>>();
+        let sprites = sprites.iter().collect::<Vec<_>>();
+        assert_eq!(unique_class_names("icon-", &sprites),
+            vec!["icon-a-b", "icon-a-b-2", "icon-a-b-2-2", "icon-a-b-3"]);
+    }
+}
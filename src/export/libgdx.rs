@@ -0,0 +1,108 @@
+//! libGDX `TextureAtlas` text format.
+
+use std::io::{self, Write};
+
+use super::strip_extension;
+use crate::atlas::AtlasMetadata;
+
+/// Splits a sprite name into its region name and sequence index, so `walk_3.png` becomes
+/// `("walk", 3)`. Names without a trailing `_N` get index -1, which libGDX treats as unindexed.
+pub fn region_name(name: &str) -> (&str, i32) {
+    let stem = strip_extension(name);
+    if let Some(underscore) = stem.rfind('_') {
+        let suffix = &stem[underscore + 1..];
+        if !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(index) = suffix.parse::<i32>() {
+                return (&stem[..underscore], index);
+            }
+        }
+    }
+    (stem, -1)
+}
+
+pub fn write_atlas<W: Write>(metadata: &AtlasMetadata, mut writer: W) -> io::Result<()> {
+    for (index, page) in metadata.pages.iter().enumerate() {
+        writeln!(writer)?;
+        writeln!(writer, "{}", page.image)?;
+        writeln!(writer, "size: {},{}", page.width, page.height)?;
+        writeln!(writer, "format: RGBA8888")?;
+        writeln!(writer, "filter: Nearest,Nearest")?;
+        writeln!(writer, "repeat: none")?;
+        if metadata.premultiplied_alpha {
+            writeln!(writer, "pma: true")?;
+        }
+
+        for sprite in metadata.sprites.iter().filter(|sprite| sprite.page == index) {
+            let (name, index) = region_name(&sprite.name);
+            writeln!(writer, "{}", name)?;
+            writeln!(writer, "  rotate: {}", sprite.rotated)?;
+            writeln!(writer, "  xy: {}, {}", sprite.x, sprite.y)?;
+            writeln!(writer, "  size: {}, {}", sprite.trimmed_width(), sprite.trimmed_height())?;
+            writeln!(writer, "  orig: {}, {}", sprite.source_width, sprite.source_height)?;
+            // libGDX measures the offset from the bottom left corner of the original image.
+            writeln!(writer, "  offset: {}, {}", sprite.offset_x,
+                sprite.source_height - sprite.offset_y - sprite.trimmed_height())?;
+            writeln!(writer, "  index: {}", index)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::{PageMetadata, SpriteMetadata};
+
+    #[test]
+    fn region_names_split_off_a_numeric_suffix() {
+        assert_eq!(region_name("walk_3.png"), ("walk", 3));
+        assert_eq!(region_name("anim/walk_03.png"), ("anim/walk", 3));
+        assert_eq!(region_name("walk.png"), ("walk", -1));
+        assert_eq!(region_name("walk_.png"), ("walk_", -1));
+        assert_eq!(region_name("walk_x1.png"), ("walk_x1", -1));
+        assert_eq!(region_name("walk_99999999999.png"), ("walk_99999999999", -1));
+    }
+
+    #[test]
+    fn offsets_are_measured_from_the_bottom_left() {
+        let trimmed = SpriteMetadata {
+            name: "a.png".to_string(),
+            page: 0,
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 6,
+            rotated: false,
+            trimmed: true,
+            offset_x: 2,
+            offset_y: 3,
+            source_width: 16,
+            source_height: 16,
+            alias_of: None,
+        };
+        let rotated = SpriteMetadata {
+            name: "b.png".to_string(),
+            x: 10,
+            width: 6,
+            height: 10,
+            rotated: true,
+            ..trimmed.clone()
+        };
+        let mut metadata = AtlasMetadata::new();
+        metadata.pages.push(PageMetadata {
+            image: "sprites.png".to_string(),
+            hidpi_image: None,
+            width: 16,
+            height: 10,
+            layout: None,
+        });
+        metadata.sprites = vec![trimmed, rotated];
+        let mut output = Vec::new();
+        write_atlas(&metadata, &mut output).unwrap();
+        let offsets = String::from_utf8(output).unwrap().lines()
+            .filter(|line| line.starts_with("  offset:"))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec!["  offset: 2, 7", "  offset: 2, 7"]);
+    }
+}
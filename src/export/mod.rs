@@ -0,0 +1,133 @@
+use std::fs;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::atlas::AtlasMetadata;
+use crate::options::InputOptions;
+
+pub mod css;
+pub mod libgdx;
+pub mod rust;
+pub mod starling;
+pub mod texture_packer;
+
+/// Returns a sprite name without its file extension, e.g. `anim/walk_0.png` becomes `anim/walk_0`.
+pub fn strip_extension(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(dot) if dot > name.rfind('/').map_or(0, |slash| slash + 1) => &name[..dot],
+        _ => name,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum ExportFormat {
+    JsonHash,
+    JsonArray,
+    LibGdx,
+    Starling,
+    Rust,
+    Css,
+    Scss,
+}
+
+impl ExportFormat {
+    pub const NAMES: &'static [&'static str] = &["json-hash", "json-array", "libgdx", "starling", "rust", "css", "scss"];
+
+    /// The name the format is chosen by on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::JsonHash => "json-hash",
+            ExportFormat::JsonArray => "json-array",
+            ExportFormat::LibGdx => "libgdx",
+            ExportFormat::Starling => "starling",
+            ExportFormat::Rust => "rust",
+            ExportFormat::Css => "css",
+            ExportFormat::Scss => "scss",
+        }
+    }
+
+    pub fn file_name(self, stem: &str) -> String {
+        match self {
+            ExportFormat::JsonHash => format!("{}-hash.json", stem),
+            ExportFormat::JsonArray => format!("{}-array.json", stem),
+            ExportFormat::LibGdx => format!("{}.atlas", stem),
+            ExportFormat::Starling => format!("{}.xml", stem),
+            ExportFormat::Rust => format!("{}.rs", stem),
+            ExportFormat::Css => format!("{}.css", stem),
+            ExportFormat::Scss => format!("_{}.scss", stem),
+        }
+    }
+
+    /// Whether a single file of this format can describe every page of a multi-page atlas.
+    /// Formats that can't are written once per page, named after each page image.
+    pub fn supports_pages(self) -> bool {
+        match self {
+            ExportFormat::JsonHash | ExportFormat::JsonArray | ExportFormat::Starling => false,
+            ExportFormat::LibGdx | ExportFormat::Rust | ExportFormat::Css | ExportFormat::Scss => true,
+        }
+    }
+
+    /// Whether the format can describe sprites stored rotated in the atlas. CSS can only show
+    /// a plain rectangle of the atlas as an element's background, and libGDX expects regions
+    /// rotated counter-clockwise while the atlas stores them rotated clockwise.
+    pub fn supports_rotation(self) -> bool {
+        !matches!(self, ExportFormat::Css | ExportFormat::Scss | ExportFormat::LibGdx)
+    }
+
+    pub fn write<W: Write>(self, metadata: &AtlasMetadata, config: &InputOptions, writer: W) -> io::Result<()> {
+        match self {
+            ExportFormat::JsonHash => texture_packer::write_hash(metadata, writer),
+            ExportFormat::JsonArray => texture_packer::write_array(metadata, writer),
+            ExportFormat::LibGdx => libgdx::write_atlas(metadata, writer),
+            ExportFormat::Starling => starling::write_xml(metadata, writer),
+            ExportFormat::Rust => rust::write_module(metadata, writer),
+            ExportFormat::Css => css::write_css(metadata, &config.css_prefix, writer),
+            ExportFormat::Scss => css::write_scss(metadata, &config.css_prefix, writer),
+        }
+    }
+
+    fn write_file(self, metadata: &AtlasMetadata, config: &InputOptions, file_name: &str) -> io::Result<()> {
+        let file = fs::File::create(file_name)?;
+        let mut writer = io::BufWriter::new(file);
+        self.write(metadata, config, &mut writer)?;
+        writer.flush()
+    }
+
+    /// Names of the files [`export`](Self::export) writes for `metadata`.
+    pub fn output_files(self, metadata: &AtlasMetadata, stem: &str) -> Vec<String> {
+        if metadata.pages.len() <= 1 || self.supports_pages() {
+            vec![self.file_name(stem)]
+        } else {
+            metadata.pages.iter().map(|page| self.file_name(page.stem())).collect()
+        }
+    }
+
+    pub fn export(self, metadata: &AtlasMetadata, config: &InputOptions, stem: &str) -> io::Result<()> {
+        if metadata.pages.len() <= 1 || self.supports_pages() {
+            return self.write_file(metadata, config, &self.file_name(stem));
+        }
+        for (index, page) in metadata.pages.iter().enumerate() {
+            self.write_file(&metadata.page(index), config, &self.file_name(page.stem()))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ExportFormat, String> {
+        match s {
+            "json-hash" => Ok(ExportFormat::JsonHash),
+            "json-array" => Ok(ExportFormat::JsonArray),
+            "libgdx" => Ok(ExportFormat::LibGdx),
+            "starling" => Ok(ExportFormat::Starling),
+            "rust" => Ok(ExportFormat::Rust),
+            "css" => Ok(ExportFormat::Css),
+            "scss" => Ok(ExportFormat::Scss),
+            _ => Err(format!("Unknown export format '{}'", s)),
+        }
+    }
+}
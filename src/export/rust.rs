@@ -0,0 +1,231 @@
+//! Rust source module with a `SpriteId` enum and constant region tables.
+
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use super::strip_extension;
+use crate::atlas::{AtlasMetadata, SpriteMetadata};
+
+/// Converts a sprite name such as `ui/close-button.png` into a CamelCase identifier
+/// (`UiCloseButton`). Every non-alphanumeric character starts a new word and the file
+/// extension is dropped.
+pub fn identifier(name: &str) -> String {
+    let stem = strip_extension(name);
+    let mut ident = String::with_capacity(stem.len());
+    for word in stem.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            ident.push(first.to_ascii_uppercase());
+            ident.extend(chars);
+        }
+    }
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) || ident == "Self" {
+        ident.insert_str(0, "Sprite");
+    }
+    ident
+}
+
+/// Assigns a unique identifier to every sprite, appending a counter when two names map to
+/// the same identifier. Sprites must already be in a stable order for the result to be stable.
+fn unique_identifiers(sprites: &[&SpriteMetadata]) -> Vec<String> {
+    let mut used = HashSet::new();
+    sprites.iter()
+        .map(|sprite| {
+            let base = identifier(&sprite.name);
+            let mut ident = base.clone();
+            let mut counter = 2;
+            while !used.insert(ident.clone()) {
+                ident = format!("{}{}", base, counter);
+                counter += 1;
+            }
+            ident
+        })
+        .collect()
+}
+
+fn float_literal(value: f64) -> String {
+    format!("{:?}", value as f32)
+}
+
+pub fn write_module<W: Write>(metadata: &AtlasMetadata, mut writer: W) -> io::Result<()> {
+    let mut sprites = metadata.sprites.iter().collect::<Vec<_>>();
+    sprites.sort_by(|a, b| a.name.cmp(&b.name));
+    let identifiers = unique_identifiers(&sprites);
+    let count = sprites.len();
+
+    writeln!(writer, "// Generated by texture-atlas {}. Do not edit.", env!("CARGO_PKG_VERSION"))?;
+    writeln!(writer)?;
+    writeln!(writer, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]")?;
+    writeln!(writer, "pub struct Region {{")?;
+    writeln!(writer, "    pub top: u32,")?;
+    writeln!(writer, "    pub left: u32,")?;
+    writeln!(writer, "    pub width: u32,")?;
+    writeln!(writer, "    pub height: u32,")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "#[derive(Clone, Copy, Debug, PartialEq, Default)]")?;
+    writeln!(writer, "pub struct Uv {{")?;
+    writeln!(writer, "    pub u0: f32,")?;
+    writeln!(writer, "    pub v0: f32,")?;
+    writeln!(writer, "    pub u1: f32,")?;
+    writeln!(writer, "    pub v1: f32,")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "/// Where a sprite's packed pixels sit inside its untrimmed source image.")?;
+    writeln!(writer, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]")?;
+    writeln!(writer, "pub struct Frame {{")?;
+    writeln!(writer, "    pub offset_x: u32,")?;
+    writeln!(writer, "    pub offset_y: u32,")?;
+    writeln!(writer, "    pub source_width: u32,")?;
+    writeln!(writer, "    pub source_height: u32,")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "#[derive(Clone, Copy, Debug, PartialEq, Eq)]")?;
+    writeln!(writer, "pub struct Page {{")?;
+    writeln!(writer, "    pub image: &'static str,")?;
+    writeln!(writer, "    pub width: u32,")?;
+    writeln!(writer, "    pub height: u32,")?;
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+    writeln!(writer, "/// Whether the page images store color premultiplied by alpha.")?;
+    writeln!(writer, "pub const PREMULTIPLIED_ALPHA: bool = {};", metadata.premultiplied_alpha)?;
+    writeln!(writer)?;
+    writeln!(writer, "pub const PAGES: [Page; {}] = [", metadata.pages.len())?;
+    for page in &metadata.pages {
+        writeln!(writer, "    Page {{ image: {:?}, width: {}, height: {} }},", page.image, page.width, page.height)?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]")?;
+    writeln!(writer, "pub enum SpriteId {{")?;
+    for ident in &identifiers {
+        writeln!(writer, "    {},", ident)?;
+    }
+    writeln!(writer, "}}")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "pub const SPRITE_NAMES: [&str; {}] = [", count)?;
+    for sprite in &sprites {
+        writeln!(writer, "    {:?},", sprite.name)?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "pub const SPRITE_PAGES: [usize; {}] = [", count)?;
+    for sprite in &sprites {
+        writeln!(writer, "    {},", sprite.page)?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "pub const SPRITE_REGIONS: [Region; {}] = [", count)?;
+    for sprite in &sprites {
+        writeln!(writer, "    Region {{ top: {}, left: {}, width: {}, height: {} }},",
+            sprite.y, sprite.x, sprite.width, sprite.height)?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "pub const SPRITE_ROTATED: [bool; {}] = [", count)?;
+    for sprite in &sprites {
+        writeln!(writer, "    {},", sprite.rotated)?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "pub const SPRITE_FRAMES: [Frame; {}] = [", count)?;
+    for sprite in &sprites {
+        writeln!(writer, "    Frame {{ offset_x: {}, offset_y: {}, source_width: {}, source_height: {} }},",
+            sprite.offset_x, sprite.offset_y, sprite.source_width, sprite.source_height)?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "pub const SPRITE_UVS: [Uv; {}] = [", count)?;
+    for sprite in &sprites {
+        let width = f64::from(metadata.pages[sprite.page].width);
+        let height = f64::from(metadata.pages[sprite.page].height);
+        writeln!(writer, "    Uv {{ u0: {}, v0: {}, u1: {}, v1: {} }},",
+            float_literal(f64::from(sprite.x) / width),
+            float_literal(f64::from(sprite.y) / height),
+            float_literal(f64::from(sprite.x + sprite.width) / width),
+            float_literal(f64::from(sprite.y + sprite.height) / height))?;
+    }
+    writeln!(writer, "];")?;
+    writeln!(writer)?;
+
+    writeln!(writer, "impl SpriteId {{")?;
+    writeln!(writer, "    pub const ALL: [SpriteId; {}] = [", count)?;
+    for ident in &identifiers {
+        writeln!(writer, "        SpriteId::{},", ident)?;
+    }
+    writeln!(writer, "    ];")?;
+    writeln!(writer)?;
+    writeln!(writer, "    pub const fn name(self) -> &'static str {{")?;
+    writeln!(writer, "        SPRITE_NAMES[self as usize]")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "    pub const fn page(self) -> Page {{")?;
+    writeln!(writer, "        PAGES[SPRITE_PAGES[self as usize]]")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "    pub const fn region(self) -> Region {{")?;
+    writeln!(writer, "        SPRITE_REGIONS[self as usize]")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "    /// Whether the sprite is stored rotated 90 degrees clockwise in its region.")?;
+    writeln!(writer, "    pub const fn rotated(self) -> bool {{")?;
+    writeln!(writer, "        SPRITE_ROTATED[self as usize]")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "    pub const fn frame(self) -> Frame {{")?;
+    writeln!(writer, "        SPRITE_FRAMES[self as usize]")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer)?;
+    writeln!(writer, "    pub const fn uv(self) -> Uv {{")?;
+    writeln!(writer, "        SPRITE_UVS[self as usize]")?;
+    writeln!(writer, "    }}")?;
+    writeln!(writer, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(name: &str) -> SpriteMetadata {
+        SpriteMetadata {
+            name: name.to_string(),
+            page: 0,
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            rotated: false,
+            trimmed: false,
+            offset_x: 0,
+            offset_y: 0,
+            source_width: 1,
+            source_height: 1,
+            alias_of: None,
+        }
+    }
+
+    #[test]
+    fn identifiers_are_camel_case_and_valid() {
+        assert_eq!(identifier("ui/close-button.png"), "UiCloseButton");
+        assert_eq!(identifier("walk_01.png"), "Walk01");
+        assert_eq!(identifier("3d.png"), "Sprite3d");
+        assert_eq!(identifier("self.png"), "SpriteSelf");
+        assert_eq!(identifier("---.png"), "Sprite");
+    }
+
+    #[test]
+    fn colliding_identifiers_get_a_counter() {
+        let sprites = ["a.png", "a_.png", "a-2.png", "A.png"].iter()
+            .map(|name| sprite(name))
+            .collect::<Vec<_>>();
+        let sprites = sprites.iter().collect::<Vec<_>>();
+        assert_eq!(unique_identifiers(&sprites), vec!["A", "A2", "A22", "A3"]);
+    }
+}
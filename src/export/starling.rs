@@ -0,0 +1,38 @@
+//! Starling/Sparrow XML `TextureAtlas` format.
+
+use std::io::{self, Write};
+
+use crate::atlas::AtlasMetadata;
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn write_xml<W: Write>(metadata: &AtlasMetadata, mut writer: W) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    // An atlas without pages has no image, so the otherwise required path is left out.
+    let image_path = metadata.pages.first()
+        .map(|page| format!(r#" imagePath="{}""#, escape_attribute(&page.image)))
+        .unwrap_or_default();
+    writeln!(writer, r#"<TextureAtlas{} premultipliedAlpha="{}">"#, image_path,
+        metadata.premultiplied_alpha)?;
+    for sprite in &metadata.sprites {
+        writeln!(writer,
+            r#"    <SubTexture name="{}" x="{}" y="{}" width="{}" height="{}" frameX="{}" frameY="{}" frameWidth="{}" frameHeight="{}" rotated="{}"/>"#,
+            escape_attribute(&sprite.name), sprite.x, sprite.y, sprite.width, sprite.height,
+            -i64::from(sprite.offset_x), -i64::from(sprite.offset_y), sprite.source_width,
+            sprite.source_height, sprite.rotated)?;
+    }
+    writeln!(writer, "</TextureAtlas>")
+}
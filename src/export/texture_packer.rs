@@ -0,0 +1,108 @@
+//! TexturePacker "JSON Hash" and "JSON Array" layouts, as read by Phaser and PixiJS.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::atlas::{AtlasMetadata, SpriteMetadata};
+
+#[derive(Serialize)]
+struct Rect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct Size {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Frame {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    frame: Rect,
+    rotated: bool,
+    trimmed: bool,
+    sprite_source_size: Rect,
+    source_size: Size,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    app: &'static str,
+    version: &'static str,
+    /// Absent for an atlas without pages, which has no image to load.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    format: &'static str,
+    size: Size,
+    scale: &'static str,
+    premultiplied_alpha: bool,
+}
+
+#[derive(Serialize)]
+struct Document<F> {
+    frames: F,
+    meta: Meta,
+}
+
+impl Frame {
+    fn new(filename: Option<String>, sprite: &SpriteMetadata) -> Frame {
+        Frame {
+            filename,
+            frame: Rect { x: sprite.x, y: sprite.y, w: sprite.trimmed_width(), h: sprite.trimmed_height() },
+            rotated: sprite.rotated,
+            trimmed: sprite.trimmed,
+            sprite_source_size: Rect {
+                x: sprite.offset_x,
+                y: sprite.offset_y,
+                w: sprite.trimmed_width(),
+                h: sprite.trimmed_height(),
+            },
+            source_size: Size { w: sprite.source_width, h: sprite.source_height },
+        }
+    }
+}
+
+impl Meta {
+    fn new(metadata: &AtlasMetadata) -> Meta {
+        let page = metadata.pages.first();
+        Meta {
+            app: "texture-atlas",
+            version: env!("CARGO_PKG_VERSION"),
+            image: page.map(|page| page.image.clone()),
+            format: "RGBA8888",
+            size: Size {
+                w: page.map_or(0, |page| page.width),
+                h: page.map_or(0, |page| page.height),
+            },
+            scale: "1",
+            premultiplied_alpha: metadata.premultiplied_alpha,
+        }
+    }
+}
+
+pub fn write_hash<W: Write>(metadata: &AtlasMetadata, writer: W) -> io::Result<()> {
+    let frames = metadata.sprites.iter()
+        .map(|sprite| (sprite.name.clone(), Frame::new(None, sprite)))
+        .collect::<BTreeMap<_, _>>();
+    let document = Document { frames, meta: Meta::new(metadata) };
+    serde_json::to_writer_pretty(writer, &document)?;
+    Ok(())
+}
+
+pub fn write_array<W: Write>(metadata: &AtlasMetadata, writer: W) -> io::Result<()> {
+    let frames = metadata.sprites.iter()
+        .map(|sprite| Frame::new(Some(sprite.name.clone()), sprite))
+        .collect::<Vec<_>>();
+    let document = Document { frames, meta: Meta::new(metadata) };
+    serde_json::to_writer_pretty(writer, &document)?;
+    Ok(())
+}
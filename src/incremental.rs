@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::atlas::{AtlasMetadata, SpriteMetadata};
+use crate::input::Sprite;
+use crate::packer::{BoxedPacker, PackerSettings};
+use crate::spatial_tree::Region;
+
+/// Pages rebuilt around a previous layout.
+pub struct Update {
+    pub pages: Vec<BoxedPacker<Sprite>>,
+    /// Sprites left exactly where they were.
+    pub kept: usize,
+    /// Sprites taken out of the layout because they were deleted or changed size.
+    pub removed: usize,
+    /// New and resized sprites packed into the free space.
+    pub inserted: usize,
+}
+
+/// Why the previous layout couldn't be reused. Hands the sprites back for a full repack.
+pub struct NeedsRepack {
+    pub reason: &'static str,
+    pub sprites: Vec<Sprite>,
+}
+
+/// Packs `sprites` around the layout saved in `previous`. Sprites whose name and packed size
+/// are unchanged keep their page and position, deleted and resized ones are removed, and the
+/// rest are inserted into the free space of the existing pages. `name` turns a source path into
+/// the sprite name used in the metadata.
+pub fn update_pages<F>(previous: &AtlasMetadata, sprites: Vec<Sprite>, settings: &PackerSettings,
+    name: F) -> Result<Update, NeedsRepack>
+    where F: Fn(&Path) -> String
+{
+    let names = sprites.iter().map(|sprite| name(&sprite.path)).collect::<Vec<_>>();
+    let current = names.iter().cloned().zip(&sprites).collect::<HashMap<_, _>>();
+    let (mut layouts, removed) = match plan_pages(previous, &current, settings) {
+        Ok(plan) => plan,
+        Err(reason) => return Err(NeedsRepack { reason, sprites }),
+    };
+
+    let kept = layouts.iter().map(|layout| layout.len()).sum::<usize>();
+    let placed = layouts.iter()
+        .flat_map(|layout| layout.placements().into_iter().map(|(name, _)| name.clone()))
+        .collect::<HashSet<_>>();
+    if placed.len() != kept {
+        return Err(NeedsRepack { reason: "the previous layout places a sprite twice", sprites });
+    }
+    let mut inserted = 0;
+    'sprites: for (sprite_name, sprite) in names.iter().zip(&sprites) {
+        if placed.contains(sprite_name) {
+            continue;
+        }
+        let (width, height) = sprite.image.dimensions();
+        for layout in layouts.iter_mut() {
+            if layout.insert(sprite_name.clone(), width, height).is_ok() {
+                inserted += 1;
+                continue 'sprites;
+            }
+        }
+        return Err(NeedsRepack { reason: "new sprites don't fit in the existing pages", sprites });
+    }
+
+    let mut by_name = names.into_iter().zip(sprites).collect::<HashMap<_, _>>();
+    let pages = layouts.iter()
+        .filter(|layout| !layout.is_empty())
+        .map(|layout| {
+            let tree = layout.spatial_tree()
+                .expect("layouts are spatial trees")
+                .map(|name| by_name.remove(name).expect("every placed sprite is loaded"));
+            settings.wrap_tree(tree)
+        })
+        .collect();
+    Ok(Update { pages, kept, removed, inserted })
+}
+
+/// Restores the saved page layouts as packers of sprite names, minus the sprites that are gone
+/// or changed size. Also returns how many were removed.
+fn plan_pages(previous: &AtlasMetadata, current: &HashMap<String, &Sprite>,
+    settings: &PackerSettings) -> Result<(Vec<BoxedPacker<String>>, usize), &'static str>
+{
+    if previous.pages.is_empty() {
+        return Err("the previous atlas has no pages");
+    }
+    let mut layouts = Vec::with_capacity(previous.pages.len());
+    let mut removed = 0;
+    for (index, page) in previous.pages.iter().enumerate() {
+        let mut tree = match &page.layout {
+            Some(layout) => layout.clone(),
+            None => return Err("the previous atlas was saved without a layout"),
+        };
+        let entries = previous.sprites.iter()
+            .filter(|entry| entry.page == index && entry.alias_of.is_none())
+            .map(|entry| (entry.name.as_str(), entry))
+            .collect::<HashMap<_, _>>();
+        let unchanged = |name: &String| match (entries.get(name.as_str()), current.get(name)) {
+            (Some(entry), Some(sprite)) =>
+                (entry.trimmed_width(), entry.trimmed_height()) == sprite.image.dimensions(),
+            _ => false,
+        };
+        let stale = tree.iter()
+            .map(|(name, _)| name)
+            .filter(|name| !unchanged(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in &stale {
+            tree.remove(name);
+        }
+        removed += stale.len();
+
+        let layout = settings.wrap_tree(tree);
+        let moved = layout.placements().into_iter()
+            .any(|(name, region)| !same_region(entries[name.as_str()], &region));
+        if moved {
+            return Err("the spacing settings changed since the previous atlas");
+        }
+        layouts.push(layout);
+    }
+    Ok((layouts, removed))
+}
+
+fn same_region(entry: &SpriteMetadata, region: &Region) -> bool {
+    (entry.x, entry.y, entry.width, entry.height) == (region.left, region.top, region.width, region.height)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::RgbaImage;
+
+    use super::*;
+    use crate::packer::PackerKind;
+
+    fn sprite(name: &str, width: u32, height: u32) -> Sprite {
+        Sprite::new(PathBuf::from(name), RgbaImage::new(width, height))
+    }
+
+    fn name(path: &Path) -> String {
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Describes packed pages the way sprites.json does, layouts included.
+    fn metadata(pages: &[BoxedPacker<Sprite>]) -> AtlasMetadata {
+        let mut metadata = AtlasMetadata::new();
+        for (index, page) in pages.iter().enumerate() {
+            metadata.add_page(page.as_ref(), &format!("sprites_{}.png", index), name);
+            metadata.pages[index].layout = page.spatial_tree()
+                .map(|tree| tree.map(|sprite| name(&sprite.path)));
+        }
+        metadata
+    }
+
+    fn pack(sprites: Vec<Sprite>, settings: &PackerSettings) -> AtlasMetadata {
+        let items = sprites.into_iter().map(|sprite| {
+            let (width, height) = sprite.image.dimensions();
+            (sprite, width, height)
+        });
+        let (pages, rejected) = PackerKind::SpatialTree.pack_pages(items, settings);
+        assert!(rejected.is_empty());
+        metadata(&pages)
+    }
+
+    fn positions(metadata: &AtlasMetadata) -> HashMap<String, (usize, u32, u32)> {
+        metadata.sprites.iter()
+            .map(|sprite| (sprite.name.clone(), (sprite.page, sprite.x, sprite.y)))
+            .collect()
+    }
+
+    fn squares(count: usize, side: u32) -> Vec<Sprite> {
+        (0..count).map(|i| sprite(&format!("s{:02}.png", i), side, side)).collect()
+    }
+
+    #[test]
+    fn unchanged_sprites_stay_in_place() {
+        let settings = PackerSettings { padding: 1, ..PackerSettings::default() };
+        let previous = pack(squares(16, 20), &settings);
+        let mut sprites = squares(16, 20);
+        sprites.remove(5);
+        sprites[0] = sprite("s00.png", 12, 12);
+        sprites.push(sprite("new.png", 8, 8));
+
+        let update = update_pages(&previous, sprites, &settings, name).ok().unwrap();
+        assert_eq!((update.kept, update.removed, update.inserted), (14, 2, 2));
+        let before = positions(&previous);
+        let after = positions(&metadata(&update.pages));
+        assert_eq!(after.len(), 16);
+        for (sprite, position) in &after {
+            if sprite.as_str() != "s00.png" && sprite.as_str() != "new.png" {
+                assert_eq!(before[sprite], *position, "{} moved", sprite);
+            }
+        }
+    }
+
+    #[test]
+    fn growing_a_page_keeps_existing_positions() {
+        let settings = PackerSettings::default();
+        let previous = pack(squares(13, 32), &settings);
+        let mut sprites = squares(13, 32);
+        sprites.push(sprite("icon.png", 50, 50));
+
+        let update = update_pages(&previous, sprites, &settings, name).ok().unwrap();
+        assert_eq!((update.kept, update.removed, update.inserted), (13, 0, 1));
+        // The page has more free area than the icon needs, but no gap it fits in.
+        let page = &previous.pages[0];
+        assert!(u64::from(page.width * page.height) - 13 * 32 * 32 >= 50 * 50);
+        let size = update.pages[0].size();
+        assert!(size.width > page.width || size.height > page.height);
+        let after = positions(&metadata(&update.pages));
+        for (sprite, position) in positions(&previous) {
+            assert_eq!(after[&sprite], position, "{} moved", sprite);
+        }
+    }
+
+    #[test]
+    fn changed_spacing_needs_a_repack() {
+        let previous = pack(squares(4, 10), &PackerSettings::default());
+        let settings = PackerSettings { padding: 2, ..PackerSettings::default() };
+        let error = update_pages(&previous, squares(4, 10), &settings, name).err().unwrap();
+        assert_eq!(error.sprites.len(), 4);
+    }
+}
@@ -1,18 +1,119 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
 use std::path;
 
+use image::RgbaImage;
+use sha2::{Digest, Sha256};
+
 use crate::options::InputOptions;
+use crate::spatial_tree::{Region, Size};
+
+/// A source image ready to be packed, possibly with its transparent margins trimmed off.
+#[derive(Clone, Debug)]
+pub struct Sprite {
+    pub path: path::PathBuf,
+    /// The pixels that get packed.
+    pub image: RgbaImage,
+    /// Size of the untrimmed source image.
+    pub source_size: Size,
+    /// Position of `image` inside the untrimmed source image.
+    pub offset: (u32, u32),
+    /// Other source files with exactly the same pixels, which share this sprite's placement.
+    pub aliases: Vec<path::PathBuf>,
+}
+
+impl Sprite {
+    pub fn new(path: path::PathBuf, image: RgbaImage) -> Sprite {
+        let (width, height) = image.dimensions();
+        Sprite {
+            path,
+            image,
+            source_size: Size { width, height },
+            offset: (0, 0),
+            aliases: Vec::new(),
+        }
+    }
+
+    /// Crops the image down to the pixels with alpha above `threshold`, remembering where they
+    /// came from. A fully transparent image is reduced to its top left pixel.
+    pub fn trim(mut self, threshold: u8) -> Sprite {
+        let bounds = opaque_bounds(&self.image, threshold)
+            .unwrap_or_else(|| Region::new(0, 0, 1, 1));
+        let image = image::imageops::crop(&mut self.image, bounds.left, bounds.top,
+            bounds.width, bounds.height).to_image();
+        Sprite {
+            image,
+            offset: (self.offset.0 + bounds.left, self.offset.1 + bounds.top),
+            ..self
+        }
+    }
+
+    pub fn is_trimmed(&self) -> bool {
+        self.image.dimensions() != (self.source_size.width, self.source_size.height)
+    }
+}
+
+fn pixel_hash(image: &RgbaImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.dimensions().hash(&mut hasher);
+    image.as_ref().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn same_pixels(a: &RgbaImage, b: &RgbaImage) -> bool {
+    a.dimensions() == b.dimensions() && a.as_ref() == b.as_ref()
+}
+
+/// Folds sprites with pixel-identical images into the first one seen, recording the others as
+/// its aliases. Order of the remaining sprites is preserved.
+pub fn merge_duplicates(sprites: Vec<Sprite>) -> Vec<Sprite> {
+    let mut unique: Vec<Sprite> = Vec::with_capacity(sprites.len());
+    let mut by_hash: HashMap<u64, Vec<usize>> = HashMap::new();
+    for sprite in sprites {
+        let candidates = by_hash.entry(pixel_hash(&sprite.image)).or_default();
+        let original = candidates.iter()
+            .copied()
+            .find(|&index| same_pixels(&unique[index].image, &sprite.image));
+        match original {
+            Some(index) => unique[index].aliases.push(sprite.path),
+            None => {
+                candidates.push(unique.len());
+                unique.push(sprite);
+            },
+        }
+    }
+    unique
+}
 
-fn iterate_dir<F>(config: &InputOptions, path: &path::Path, callback: &mut F)
+/// The smallest region containing every pixel with alpha above `threshold`.
+pub fn opaque_bounds(image: &RgbaImage, threshold: u8) -> Option<Region> {
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel.data[3] > threshold {
+            bounds = Some(match bounds {
+                Some((left, top, right, bottom)) => (left.min(x), top.min(y), right.max(x), bottom.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+    }
+    bounds.map(|(left, top, right, bottom)| Region::new(top, left, right - left + 1, bottom - top + 1))
+}
+
+fn iterate_dir<F>(path: &path::Path, callback: &mut F)
     where F: FnMut(&path::Path)
 {
     for entry in fs::read_dir(path)
-        .expect(&format!("{} is not a valid directory", path.display()))
+        .unwrap_or_else(|_| panic!("{} is not a valid directory", path.display()))
     {
         let entry = entry.unwrap();
         if entry.path().is_dir() {
             println!("{}", entry.path().display());
-            iterate_dir(config, &entry.path(), callback)
+            iterate_dir(&entry.path(), callback)
         } else {
             callback(&entry.path());
         }
@@ -22,13 +123,182 @@ fn iterate_dir<F>(config: &InputOptions, path: &path::Path, callback: &mut F)
 pub fn get_input_files(config: &InputOptions) -> Vec<path::PathBuf> {
     let mut paths = Vec::new();
     for directory in &config.directories {
-        iterate_dir(config, directory, &mut |path| {
+        iterate_dir(directory, &mut |path| {
             paths.push(path.to_path_buf());
         });
     }
     paths
 }
 
+/// Hash of everything a build depends on: the tool version, the options and the path and
+/// contents of every input file. Files are hashed in sorted order, so the order directories
+/// happen to be listed in doesn't matter. The hash is SHA-256 over length-prefixed fields, so it
+/// stays the same across Rust releases and platforms.
+pub fn input_hash(config: &InputOptions, files: &[path::PathBuf]) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    field(env!("CARGO_PKG_VERSION").as_bytes());
+    field(&serde_json::to_vec(&InputOptions { force: false, ..config.clone() })?);
+    let mut files = files.to_vec();
+    files.sort();
+    for file in &files {
+        field(file.to_string_lossy().as_bytes());
+        field(&fs::read(file)?);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 pub fn load_image(path: &path::Path) -> Option<image::DynamicImage> {
     image::open(path).ok()
-}
\ No newline at end of file
+}
+
+pub fn relative_name(config: &InputOptions, path: &path::Path) -> String {
+    let relative = config.directories.iter()
+        .find_map(|dir| path.strip_prefix(dir).ok())
+        .unwrap_or(path);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+/// Returned by [`check_unique_names`] when two sprites from different input directories end up
+/// with the same relative name, which the metadata would list as a single sprite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateName {
+    pub name: String,
+    pub first: path::PathBuf,
+    pub second: path::PathBuf,
+}
+
+impl fmt::Display for DuplicateName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} and {} are both named {}", self.first.display(), self.second.display(), self.name)
+    }
+}
+
+impl Error for DuplicateName {}
+
+/// Checks that no two sprites share a [`relative_name`], since sprites are identified by it in
+/// the metadata and in incremental updates.
+pub fn check_unique_names(config: &InputOptions, sprites: &[Sprite]) -> Result<(), DuplicateName> {
+    let mut seen: HashMap<String, &path::Path> = HashMap::new();
+    for sprite in sprites {
+        let name = relative_name(config, &sprite.path);
+        if let Some(first) = seen.insert(name.clone(), &sprite.path) {
+            return Err(DuplicateName { name, first: first.to_path_buf(), second: sprite.path.clone() });
+        }
+    }
+    Ok(())
+}
+
+/// If `path` names a high-DPI variant such as `button@2x.png`, returns the path of the
+/// sprite it is a variant of (`button.png`).
+pub fn hidpi_base(path: &path::Path) -> Option<path::PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let base_stem = stem.strip_suffix("@2x")?;
+    let mut base = path.with_file_name(base_stem);
+    if let Some(extension) = path.extension() {
+        base.set_extension(extension);
+    }
+    Some(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(name: &str, width: u32, height: u32, color: [u8; 4]) -> Sprite {
+        Sprite::new(name.into(), RgbaImage::from_pixel(width, height, image::Rgba(color)))
+    }
+
+    #[test]
+    fn identical_pixels_become_aliases_of_the_first_sprite() {
+        const RED: [u8; 4] = [255, 0, 0, 255];
+        const BLUE: [u8; 4] = [0, 0, 255, 255];
+        let sprites = vec![
+            sprite("a.png", 2, 2, RED),
+            sprite("b.png", 2, 2, BLUE),
+            sprite("c.png", 2, 2, RED),
+            sprite("d.png", 4, 1, RED),
+            sprite("e.png", 2, 2, BLUE),
+        ];
+        let merged = merge_duplicates(sprites).into_iter()
+            .map(|sprite| (sprite.path, sprite.aliases))
+            .collect::<Vec<_>>();
+        assert_eq!(merged, vec![
+            ("a.png".into(), vec!["c.png".into()]),
+            ("b.png".into(), vec!["e.png".into()]),
+            ("d.png".into(), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn sprites_from_different_directories_must_have_different_names() {
+        let config = InputOptions { directories: vec!["a".into(), "b".into()], ..InputOptions::default() };
+        let sprites = vec![
+            sprite("a/icon.png", 1, 1, [0; 4]),
+            sprite("b/button.png", 1, 1, [0; 4]),
+            sprite("b/icon.png", 1, 1, [0; 4]),
+        ];
+        assert_eq!(check_unique_names(&config, &sprites[..2]), Ok(()));
+        assert_eq!(check_unique_names(&config, &sprites), Err(DuplicateName {
+            name: "icon.png".into(),
+            first: "a/icon.png".into(),
+            second: "b/icon.png".into(),
+        }));
+    }
+
+    #[test]
+    fn trim_crops_to_pixels_above_the_threshold() {
+        let mut image = RgbaImage::new(5, 4);
+        image.put_pixel(1, 1, image::Rgba([255, 0, 0, 200]));
+        image.put_pixel(3, 2, image::Rgba([0, 255, 0, 11]));
+        image.put_pixel(4, 3, image::Rgba([0, 0, 255, 10]));
+        assert_eq!(opaque_bounds(&image, 10), Some(Region::new(1, 1, 3, 2)));
+        assert_eq!(opaque_bounds(&image, 0), Some(Region::new(1, 1, 4, 3)));
+        assert_eq!(opaque_bounds(&image, 255), None);
+
+        let trimmed = Sprite::new("a.png".into(), image).trim(10);
+        assert!(trimmed.is_trimmed());
+        assert_eq!(trimmed.image.dimensions(), (3, 2));
+        assert_eq!(trimmed.offset, (1, 1));
+        assert_eq!(trimmed.source_size, Size { width: 5, height: 4 });
+        assert_eq!(trimmed.image.get_pixel(0, 0).data, [255, 0, 0, 200]);
+        assert_eq!(trimmed.image.get_pixel(2, 1).data, [0, 255, 0, 11]);
+
+        let again = trimmed.trim(100);
+        assert_eq!(again.image.dimensions(), (1, 1));
+        assert_eq!(again.offset, (1, 1));
+    }
+
+    #[test]
+    fn trim_reduces_a_transparent_sprite_to_one_pixel() {
+        let trimmed = sprite("a.png", 3, 3, [0, 0, 0, 0]).trim(0);
+        assert_eq!(trimmed.image.dimensions(), (1, 1));
+        assert_eq!(trimmed.offset, (0, 0));
+        assert!(trimmed.is_trimmed());
+        assert!(!sprite("b.png", 3, 3, [0, 0, 0, 255]).trim(0).is_trimmed());
+    }
+
+    #[test]
+    fn input_hash_depends_on_contents_and_options_but_not_force_or_order() {
+        let dir = std::env::temp_dir().join(format!("texture_atlas_hash_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.png"), dir.join("b.png"));
+        fs::write(&a, b"first").unwrap();
+        fs::write(&b, b"second").unwrap();
+        let config = InputOptions::default();
+        let hash = input_hash(&config, &[a.clone(), b.clone()]).unwrap();
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(input_hash(&config, &[b.clone(), a.clone()]).unwrap(), hash);
+        let forced = InputOptions { force: true, ..config.clone() };
+        assert_eq!(input_hash(&forced, &[a.clone(), b.clone()]).unwrap(), hash);
+        let padded = InputOptions { padding: 1, ..config.clone() };
+        assert_ne!(input_hash(&padded, &[a.clone(), b.clone()]).unwrap(), hash);
+        fs::write(&b, b"changed").unwrap();
+        assert_ne!(input_hash(&config, &[a.clone(), b.clone()]).unwrap(), hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,10 @@
+pub mod options;
+pub mod input;
+pub mod spatial_tree;
+pub mod render;
+pub mod atlas;
+pub mod incremental;
+pub mod export;
+pub mod packer;
+pub mod maxrects;
+pub mod skyline;
@@ -1,10 +1,9 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
 use std::path::Path;
-use std::u32;
 
-pub mod options;
-pub mod input;
-pub mod spatial_tree;
-pub mod render;
+use texture_atlas::{atlas, incremental, input, options, packer, render};
 
 use rand::distributions::Distribution;
 
@@ -18,24 +17,169 @@ pub fn sprite_sheet_main(config: options::InputOptions) {
     for file in &files {
         println!("{}", file.display());
     }
-    let mut images = files.iter()
-        .map(|path| (path.clone(), input::load_image(&path)))
+    let input_hash = input::input_hash(&config, &files).ok();
+    if !config.force && input_hash.as_ref().is_some_and(|hash| is_up_to_date(&config, hash)) {
+        println!("Inputs unchanged since the last build, nothing to do (use --force to rebuild)");
+        return;
+    }
+    let hidpi_files = files.iter()
+        .filter_map(|path| input::hidpi_base(path)
+            .filter(|base| files.contains(base))
+            .map(|base| (base, path.clone())))
+        .collect::<HashMap<_, _>>();
+    let files = files.into_iter()
+        .filter(|path| !hidpi_files.values().any(|variant| variant == path))
+        .collect::<Vec<_>>();
+    let sprites = files.iter()
+        .map(|path| (path.clone(), input::load_image(path)))
         .filter(|f| f.1.is_some())
-        .map(|f| (f.0, f.1.unwrap().to_rgba()))
+        .map(|f| input::Sprite::new(f.0, f.1.unwrap().to_rgba()))
         .collect::<Vec<_>>();
-    images.sort_unstable_by_key(|(_, img)| u32::MAX - img.width().max(img.height()));
+    if let Err(e) = input::check_unique_names(&config, &sprites) {
+        panic!("{}; rename one or pass their parent directory instead", e);
+    }
+    let mut sprites = input::merge_duplicates(sprites).into_iter()
+        .map(|sprite| if config.trim { sprite.trim(config.trim_threshold) } else { sprite })
+        .collect::<Vec<_>>();
+    let duplicates = sprites.iter().map(|sprite| sprite.aliases.len()).sum::<usize>();
+    if duplicates > 0 {
+        let area = |sprite: &input::Sprite|
+            u64::from(sprite.image.width()) * u64::from(sprite.image.height());
+        let saved = sprites.iter()
+            .map(|sprite| area(sprite) * sprite.aliases.len() as u64)
+            .sum::<u64>();
+        let total = saved + sprites.iter().map(area).sum::<u64>();
+        println!("Aliased {} duplicate sprites, saving {} pixels ({:.2}% of the sprite area)",
+            duplicates, saved, saved as f64 / total as f64 * 100.0);
+    }
+    sprites.sort_unstable_by_key(|sprite| u32::MAX - sprite.image.width().max(sprite.image.height()));
+
+    let sizes = sprites.iter()
+        .map(|sprite| sprite.image.dimensions())
+        .collect::<Vec<_>>();
+    let settings = config.packer_settings();
+    let (pages, rejected) = pack_sprites(&config, sprites, &settings);
+    for e in &rejected {
+        println!("Skipping {}: {}", e.item.path.display(), e);
+    }
+
+    for (index, page) in pages.iter().enumerate() {
+        let size = page.size();
+        println!("Page {}: packed {} sprites into {}x{} ({:.2}% occupancy)", index, page.len(),
+            size.width, size.height, page.occupancy()*100.0);
+    }
+    // Only MaxRects is slow enough that an extra tree pack for comparison costs little.
+    if matches!(config.packer, packer::PackerKind::MaxRects(_)) && pages.len() == 1 {
+        let occupancy = pages[0].occupancy();
+        let tree_occupancy = packer::PackerKind::SpatialTree.pack_sizes(&sizes, &settings).occupancy();
+        println!("SpatialTree occupancy: {:.2}% ({:+.2}%)", tree_occupancy*100.0,
+            (occupancy - tree_occupancy)*100.0);
+    }
 
-    let mut tree = spatial_tree::SpatialTree::new();
+    let hidpi_images = hidpi_files.into_iter()
+        .filter_map(|(base, path)| input::load_image(&path).map(|img| (base, img.to_rgba())))
+        .collect::<HashMap<_, _>>();
+    // A previous sprites.json with the same input hash would vouch for outputs left half
+    // written if this run fails, so it goes before anything is overwritten.
+    if let Err(e) = fs::remove_file("sprites.json") {
+        if e.kind() != io::ErrorKind::NotFound {
+            panic!("Failed to remove the previous sprites.json: {}", e);
+        }
+    }
+    let render_settings = config.render_settings();
+    let mut metadata = atlas::AtlasMetadata::new();
+    metadata.premultiplied_alpha = config.premultiply_alpha;
+    metadata.input_hash = input_hash;
+    for (index, page) in pages.iter().enumerate() {
+        let stem = if pages.len() == 1 {
+            "sprites".to_string()
+        } else {
+            format!("sprites_{}", index)
+        };
+        let image = format!("{}.png", stem);
+        render::draw_sprites(page.as_ref(), &render_settings, Path::new(&image));
+        metadata.add_page(page.as_ref(), &image, |path| input::relative_name(&config, path));
+        if config.incremental {
+            metadata.pages[index].layout = page.spatial_tree()
+                .map(|tree| tree.map(|sprite| input::relative_name(&config, &sprite.path)));
+        }
+
+        if !hidpi_images.is_empty() {
+            let hidpi_image = format!("{}@2x.png", stem);
+            render::draw_hidpi_sprites(page.as_ref(), &hidpi_images, 2, &render_settings,
+                Path::new(&hidpi_image));
+            metadata.pages[index].hidpi_image = Some(hidpi_image);
+        }
+    }
 
-    for image in images {
-        let width = image.1.width();
-        let height = image.1.height();
-        tree.insert(image, width, height)
+    for format in &config.formats {
+        format.export(&metadata, &config, "sprites")
+            .unwrap_or_else(|e| panic!("Failed to write {}: {}", format.file_name("sprites"), e));
     }
+    // Written last: its input hash marks every other output as complete.
+    metadata.save(Path::new("sprites.json")).expect("Failed to write sprites.json");
+}
+
+/// Whether sprites.json was written by a build with the same inputs and every file that build
+/// wrote is still there.
+fn is_up_to_date(config: &options::InputOptions, input_hash: &str) -> bool {
+    let previous = match atlas::AtlasMetadata::load(Path::new("sprites.json")) {
+        Ok(previous) => previous,
+        Err(_) => return false,
+    };
+    if previous.input_hash.as_deref() != Some(input_hash) {
+        return false;
+    }
+    let images = previous.pages.iter()
+        .flat_map(|page| std::iter::once(page.image.clone()).chain(page.hidpi_image.clone()));
+    let exports = config.formats.iter()
+        .flat_map(|format| format.output_files(&previous, "sprites"));
+    images.chain(exports).all(|file| Path::new(&file).exists())
+}
 
-    render::draw_spatial_tree_sprites(&mut tree, Path::new("sprites.png"))
+/// Packs the sprites from scratch or, with --incremental, around the layout saved in the
+/// previous sprites.json when it can still be used.
+fn pack_sprites(config: &options::InputOptions, sprites: Vec<input::Sprite>,
+    settings: &packer::PackerSettings)
+    -> (Vec<packer::BoxedPacker<input::Sprite>>, Vec<packer::DoesNotFit<input::Sprite>>)
+{
+    let sprites = if config.incremental && config.packer != packer::PackerKind::SpatialTree {
+        println!("Repacking all sprites: incremental updates need the tree packer");
+        sprites
+    } else if config.incremental {
+        match atlas::AtlasMetadata::load(Path::new("sprites.json")) {
+            Ok(previous) => {
+                let update = incremental::update_pages(&previous, sprites, settings,
+                    |path| input::relative_name(config, path));
+                match update {
+                    Ok(update) => {
+                        println!("Kept {} sprites in place, removed {} and inserted {}",
+                            update.kept, update.removed, update.inserted);
+                        return (update.pages, Vec::new());
+                    },
+                    Err(e) => {
+                        println!("Repacking all sprites: {}", e.reason);
+                        e.sprites
+                    },
+                }
+            },
+            Err(e) => {
+                println!("Repacking all sprites: can't read sprites.json: {}", e);
+                sprites
+            },
+        }
+    } else {
+        sprites
+    };
+    let items = sprites.into_iter()
+        .map(|sprite| {
+            let (width, height) = sprite.image.dimensions();
+            (sprite, width, height)
+        });
+    config.packer.pack_pages(items, settings)
 }
 
+#[allow(dead_code)]
 fn generate_random_test_data(n: u32) -> Vec<(u32, u32, u32)> {
     let mut rng = rand::thread_rng();
     let dist = rand::distributions::Normal::new(80.0, 20.0);
@@ -43,11 +187,12 @@ fn generate_random_test_data(n: u32) -> Vec<(u32, u32, u32)> {
     let mut values = (0..n)
         .map(|x| (x, dist.sample(&mut rng).max(5.0) as u32, dist.sample(&mut rng).max(5.0) as u32)).collect::<Vec<_>>();
 
-    values.sort_unstable_by_key(|(i, w, h)| u32::MAX - w.max(h));
+    values.sort_unstable_by_key(|(_, w, h)| u32::MAX - w.max(h));
 
     values
 }
 
+#[allow(dead_code)]
 fn generate_discrete_test_data(n: u32, choices: &[u32]) -> Vec<(u32, u32, u32)> {
     let mut rng = rand::thread_rng();
     let dist = rand::distributions::Uniform::new(0, choices.len()-1);
@@ -55,7 +200,7 @@ fn generate_discrete_test_data(n: u32, choices: &[u32]) -> Vec<(u32, u32, u32)>
     let mut values = (0..n)
         .map(|x| (x, choices[dist.sample(&mut rng)], choices[dist.sample(&mut rng)])).collect::<Vec<_>>();
 
-    values.sort_unstable_by_key(|(i, w, h)| u32::MAX - w*h/*w.max(h)*/);
+    values.sort_unstable_by_key(|(_, w, h)| u32::MAX - w*h/*w.max(h)*/);
 
     values
 }
\ No newline at end of file
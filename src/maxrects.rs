@@ -0,0 +1,338 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::packer::{BinLimits, DoesNotFit, Packer};
+use crate::spatial_tree::{Region, Size};
+
+/// Rule used to pick which free rectangle an item is placed into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize)]
+pub enum MaxRectsHeuristic {
+    #[default]
+    BestShortSideFit,
+    BestLongSideFit,
+    BestAreaFit,
+    BottomLeft,
+    ContactPoint,
+}
+
+/// Maximal rectangles bin packer. Keeps the set of all maximal free rectangles and places
+/// each item into the one scoring best under the chosen heuristic, growing the bin when an
+/// item doesn't fit anywhere.
+#[derive(Clone, Debug)]
+pub struct MaxRects<T> {
+    heuristic: MaxRectsHeuristic,
+    width: u32,
+    height: u32,
+    limits: BinLimits,
+    free_rects: Vec<Region>,
+    used: Vec<(T, Region)>,
+}
+
+impl MaxRectsHeuristic {
+    pub const NAMES: &'static [&'static str] =
+        &["best-short-side", "best-long-side", "best-area", "bottom-left", "contact-point"];
+}
+
+impl FromStr for MaxRectsHeuristic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MaxRectsHeuristic, String> {
+        match s {
+            "best-short-side" => Ok(MaxRectsHeuristic::BestShortSideFit),
+            "best-long-side" => Ok(MaxRectsHeuristic::BestLongSideFit),
+            "best-area" => Ok(MaxRectsHeuristic::BestAreaFit),
+            "bottom-left" => Ok(MaxRectsHeuristic::BottomLeft),
+            "contact-point" => Ok(MaxRectsHeuristic::ContactPoint),
+            _ => Err(format!("Unknown MaxRects heuristic '{}'", s)),
+        }
+    }
+}
+
+/// Length of the overlap between the intervals `[start1, end1)` and `[start2, end2)`.
+fn common_interval_length(start1: u32, end1: u32, start2: u32, end2: u32) -> u32 {
+    if end1 < start2 || end2 < start1 {
+        0
+    } else {
+        end1.min(end2).saturating_sub(start1.max(start2))
+    }
+}
+
+impl<T> MaxRects<T> {
+    pub fn new(heuristic: MaxRectsHeuristic) -> MaxRects<T> {
+        MaxRects::with_initial_size(heuristic, 0, 0)
+    }
+
+    pub fn with_initial_size(heuristic: MaxRectsHeuristic, width: u32, height: u32) -> MaxRects<T> {
+        let free_rects = if width > 0 && height > 0 {
+            vec![Region::new(0, 0, width, height)]
+        } else {
+            Vec::new()
+        };
+        MaxRects {
+            heuristic,
+            width,
+            height,
+            limits: BinLimits::default(),
+            free_rects,
+            used: Vec::new(),
+        }
+    }
+
+    pub fn with_limits(heuristic: MaxRectsHeuristic, limits: BinLimits) -> MaxRects<T> {
+        MaxRects { limits, ..MaxRects::new(heuristic) }
+    }
+
+    pub fn limits(&self) -> &BinLimits {
+        &self.limits
+    }
+
+    pub fn heuristic(&self) -> MaxRectsHeuristic {
+        self.heuristic
+    }
+
+    fn contact_score(&self, region: &Region) -> u64 {
+        let mut score = 0;
+        if region.left == 0 || region.right() == self.width {
+            score += u64::from(region.height);
+        }
+        if region.top == 0 || region.bottom() == self.height {
+            score += u64::from(region.width);
+        }
+        for (_, used) in &self.used {
+            if used.left == region.right() || used.right() == region.left {
+                score += u64::from(common_interval_length(
+                    used.top, used.bottom(), region.top, region.bottom()));
+            }
+            if used.top == region.bottom() || used.bottom() == region.top {
+                score += u64::from(common_interval_length(
+                    used.left, used.right(), region.left, region.right()));
+            }
+        }
+        score
+    }
+
+    /// Scores placing an item of the given size at the top left of `free`. Lower is better.
+    fn score(&self, free: &Region, width: u32, height: u32) -> (u64, u64) {
+        let leftover_horiz = u64::from(free.width - width);
+        let leftover_vert = u64::from(free.height - height);
+        let short_side = leftover_horiz.min(leftover_vert);
+        let long_side = leftover_horiz.max(leftover_vert);
+        match self.heuristic {
+            MaxRectsHeuristic::BestShortSideFit => (short_side, long_side),
+            MaxRectsHeuristic::BestLongSideFit => (long_side, short_side),
+            MaxRectsHeuristic::BestAreaFit => {
+                let area_fit = u64::from(free.width) * u64::from(free.height)
+                    - u64::from(width) * u64::from(height);
+                (area_fit, short_side)
+            },
+            MaxRectsHeuristic::BottomLeft => (u64::from(free.top + height), u64::from(free.left)),
+            MaxRectsHeuristic::ContactPoint => {
+                let candidate = Region::new(free.top, free.left, width, height);
+                (u64::MAX - self.contact_score(&candidate), 0)
+            },
+        }
+    }
+
+    fn find_position(&self, width: u32, height: u32) -> Option<Region> {
+        self.limits.orientations(width, height).into_iter()
+            .flat_map(|(width, height)| self.free_rects.iter()
+                .filter(move |free| free.width >= width && free.height >= height)
+                .map(move |free| (self.score(free, width, height), Region::new(free.top, free.left, width, height))))
+            .min_by_key(|(score, _)| *score)
+            .map(|(_, region)| region)
+    }
+
+    fn place(&mut self, placed: &Region) {
+        let mut new_rects = Vec::new();
+        self.free_rects.retain(|free| {
+            if !free.intersects(placed) {
+                return true;
+            }
+            if placed.left > free.left {
+                new_rects.push(Region::new(free.top, free.left, placed.left - free.left, free.height));
+            }
+            if placed.right() < free.right() {
+                new_rects.push(Region::new(free.top, placed.right(), free.right() - placed.right(), free.height));
+            }
+            if placed.top > free.top {
+                new_rects.push(Region::new(free.top, free.left, free.width, placed.top - free.top));
+            }
+            if placed.bottom() < free.bottom() {
+                new_rects.push(Region::new(placed.bottom(), free.left, free.width, free.bottom() - placed.bottom()));
+            }
+            false
+        });
+        self.add_free_rects(new_rects);
+    }
+
+    /// Adds `new_rects` to the free rectangles, dropping any rectangle contained in another.
+    /// The existing free rectangles must not contain each other, so only pairs involving a new
+    /// one need checking.
+    fn add_free_rects(&mut self, mut new_rects: Vec<Region>) {
+        let mut i = 0;
+        while i < new_rects.len() {
+            let contained = self.free_rects.iter().any(|free| free.contains(&new_rects[i]))
+                || new_rects.iter().enumerate().any(|(j, other)|
+                    j != i && other.contains(&new_rects[i]) && (other != &new_rects[i] || j < i));
+            if contained {
+                new_rects.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.free_rects.retain(|free| !new_rects.iter().any(|new| new.contains(free)));
+        self.free_rects.extend(new_rects);
+    }
+
+    /// Enlarges the bin to `new_width` x `new_height`, stretching free rectangles that touch
+    /// the old right or bottom edge and adding the newly uncovered strips.
+    fn extend(&mut self, new_width: u32, new_height: u32) {
+        let (width, height) = (self.width, self.height);
+        let (mut changed, unchanged): (Vec<_>, Vec<_>) = self.free_rects.drain(..)
+            .partition(|free| free.right() == width || free.bottom() == height);
+        for free in &mut changed {
+            if free.right() == width {
+                free.width += new_width - width;
+            }
+            if free.bottom() == height {
+                free.height += new_height - height;
+            }
+        }
+        if new_width > width {
+            changed.push(Region::new(0, width, new_width - width, new_height));
+        }
+        if new_height > height {
+            changed.push(Region::new(height, 0, new_width, new_height - height));
+        }
+        self.width = new_width;
+        self.height = new_height;
+        self.free_rects = unchanged;
+        self.add_free_rects(changed);
+    }
+
+    /// Whether an item of the given size would fit somewhere once the bin is extended to
+    /// `size`, worked out from the free rectangles as [`extend`](MaxRects::extend) would leave
+    /// them.
+    fn fits_extended(&self, size: &Size, width: u32, height: u32) -> bool {
+        let stretched = self.free_rects.iter().map(|free| (
+            if free.right() == self.width { free.width + size.width - self.width } else { free.width },
+            if free.bottom() == self.height { free.height + size.height - self.height } else { free.height },
+        ));
+        let strips = [(size.width - self.width, size.height), (size.width, size.height - self.height)];
+        let mut free = stretched.chain(strips.iter().cloned());
+        let orientations = self.limits.orientations(width, height);
+        free.any(|(free_width, free_height)| orientations.iter()
+            .any(|&(width, height)| free_width >= width && free_height >= height))
+    }
+
+    /// Extends the bin to the first growth candidate the item fits in, and returns where the
+    /// item can go.
+    fn grow(&mut self, width: u32, height: u32) -> Option<Region> {
+        let current = Size { width: self.width, height: self.height };
+        let candidate = self.limits.growth_candidates(&current, width, height).into_iter()
+            .find(|candidate| self.fits_extended(candidate, width, height))?;
+        self.extend(candidate.width, candidate.height);
+        self.find_position(width, height)
+    }
+}
+
+impl<T> Packer<T> for MaxRects<T> {
+    fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>> {
+        let region = match self.find_position(width, height) {
+            Some(region) => region,
+            None => match self.grow(width, height) {
+                Some(region) => region,
+                None => return Err(DoesNotFit { item, width, height }),
+            },
+        };
+        self.place(&region);
+        self.used.push((item, region.clone()));
+        Ok(region)
+    }
+
+    fn len(&self) -> usize {
+        self.used.len()
+    }
+
+    fn placements(&self) -> Vec<(&T, Region)> {
+        self.used.iter()
+            .map(|(item, region)| (item, region.clone()))
+            .collect()
+    }
+
+    fn size(&self) -> Size {
+        Size { width: self.width, height: self.height }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEURISTICS: [MaxRectsHeuristic; 5] = [
+        MaxRectsHeuristic::BestShortSideFit,
+        MaxRectsHeuristic::BestLongSideFit,
+        MaxRectsHeuristic::BestAreaFit,
+        MaxRectsHeuristic::BottomLeft,
+        MaxRectsHeuristic::ContactPoint,
+    ];
+
+    /// Item sizes from a fixed linear congruential sequence.
+    fn sizes(count: usize, seed: u64) -> Vec<(u32, u32)> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            1 + ((state >> 33) % 40) as u32
+        };
+        (0..count).map(|_| (next(), next())).collect()
+    }
+
+    fn assert_packed(packer: &MaxRects<usize>) {
+        let size = packer.size();
+        let bin = Region::new(0, 0, size.width, size.height);
+        let placements = packer.placements();
+        for (i, (_, a)) in placements.iter().enumerate() {
+            assert!(bin.contains(a), "{:?} is outside the {}x{} bin", a, size.width, size.height);
+            for (_, b) in &placements[i + 1..] {
+                assert!(!a.intersects(b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+        for (i, free) in packer.free_rects.iter().enumerate() {
+            assert!(bin.contains(free));
+            assert!(placements.iter().all(|(_, used)| !used.intersects(free)));
+            for (j, other) in packer.free_rects.iter().enumerate() {
+                assert!(i == j || !other.contains(free), "{:?} contains {:?}", other, free);
+            }
+        }
+    }
+
+    #[test]
+    fn placements_stay_inside_the_bin_without_overlapping() {
+        for (seed, &heuristic) in HEURISTICS.iter().enumerate() {
+            for &allow_rotation in &[false, true] {
+                let limits = BinLimits { allow_rotation, ..BinLimits::default() };
+                let mut packer = MaxRects::with_limits(heuristic, limits);
+                for (i, &(width, height)) in sizes(80, seed as u64).iter().enumerate() {
+                    let region = packer.insert(i, width, height).unwrap();
+                    assert!((region.width, region.height) == (width, height)
+                        || allow_rotation && (region.width, region.height) == (height, width));
+                }
+                assert_packed(&packer);
+            }
+        }
+    }
+
+    #[test]
+    fn items_beyond_the_maximum_size_are_handed_back() {
+        let limits = BinLimits::with_max_size(64, 64);
+        let mut packer = MaxRects::with_limits(MaxRectsHeuristic::default(), limits);
+        let rejected = sizes(40, 9).into_iter().enumerate()
+            .filter_map(|(i, (width, height))| packer.insert(i, width, height).err())
+            .collect::<Vec<_>>();
+        assert!(!rejected.is_empty());
+        assert!(packer.size().width <= 64 && packer.size().height <= 64);
+        assert_eq!(packer.len() + rejected.len(), 40);
+        assert_packed(&packer);
+    }
+}
@@ -1,13 +1,58 @@
 use std::path;
-use std::u32;
 
 use clap::{self, Arg};
+use serde::Serialize;
 
-#[derive(Default, Debug)]
+use crate::export::ExportFormat;
+use crate::maxrects::MaxRectsHeuristic;
+use crate::packer::{PackerKind, PackerSettings, SizeConstraints};
+use crate::render::RenderSettings;
+
+#[derive(Clone, Default, Debug, Serialize)]
 pub struct InputOptions {
     pub directories: Vec<path::PathBuf>,
     pub max_width: u32,
     pub max_height: u32,
+    pub formats: Vec<ExportFormat>,
+    pub css_prefix: String,
+    pub packer: PackerKind,
+    pub allow_rotation: bool,
+    pub trim: bool,
+    pub trim_threshold: u8,
+    pub padding: u32,
+    pub border: u32,
+    pub extrude: u32,
+    pub alpha_bleed: bool,
+    pub premultiply_alpha: bool,
+    pub power_of_two: bool,
+    pub square: bool,
+    pub incremental: bool,
+    pub force: bool,
+}
+
+impl InputOptions {
+    pub fn packer_settings(&self) -> PackerSettings {
+        PackerSettings {
+            max_width: self.max_width,
+            max_height: self.max_height,
+            allow_rotation: self.allow_rotation,
+            padding: self.padding,
+            border: self.border,
+            extrude: self.extrude,
+            constraints: SizeConstraints {
+                power_of_two: self.power_of_two,
+                square: self.square,
+            },
+        }
+    }
+
+    pub fn render_settings(&self) -> RenderSettings {
+        RenderSettings {
+            extrude: self.extrude,
+            alpha_bleed: self.alpha_bleed,
+            premultiply_alpha: self.premultiply_alpha,
+        }
+    }
 }
 
 pub fn parse_ops() -> InputOptions {
@@ -35,11 +80,98 @@ pub fn parse_ops() -> InputOptions {
             .value_name("MAX_HEIGHT")
             .help("Maximum height of output image")
             .default_value("32000")
+        )
+        .arg(Arg::with_name("formats")
+            .short("f")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Additional metadata formats to export")
+            .multiple(true)
+            .number_of_values(1)
+            .possible_values(ExportFormat::NAMES)
+        )
+        .arg(Arg::with_name("css_prefix")
+            .long("css-prefix")
+            .value_name("PREFIX")
+            .help("Class name prefix used by the css and scss formats")
+            .default_value("sprite-")
+        )
+        .arg(Arg::with_name("packer")
+            .short("p")
+            .long("packer")
+            .value_name("PACKER")
+            .help("Packing algorithm")
+            .possible_values(PackerKind::NAMES)
+            .default_value("tree")
+        )
+        .arg(Arg::with_name("heuristic")
+            .long("heuristic")
+            .value_name("HEURISTIC")
+            .help("Free rectangle choice heuristic for the maxrects packer")
+            .possible_values(MaxRectsHeuristic::NAMES)
+            .default_value("best-short-side")
+        )
+        .arg(Arg::with_name("rotate")
+            .long("rotate")
+            .help("Allow sprites to be rotated 90 degrees when that packs better (not with the css, scss and libgdx formats)")
+        )
+        .arg(Arg::with_name("trim")
+            .long("trim")
+            .help("Trim transparent margins off sprites before packing")
+        )
+        .arg(Arg::with_name("trim_threshold")
+            .long("trim-threshold")
+            .value_name("ALPHA")
+            .help("Pixels with alpha at or below this value count as transparent when trimming")
+            .default_value("0")
+        )
+        .arg(Arg::with_name("padding")
+            .long("padding")
+            .value_name("PIXELS")
+            .help("Empty space between neighbouring sprites")
+            .default_value("0")
+        )
+        .arg(Arg::with_name("border")
+            .long("border")
+            .value_name("PIXELS")
+            .help("Empty space between the sprites and the edge of the atlas")
+            .default_value("0")
+        )
+        .arg(Arg::with_name("extrude")
+            .long("extrude")
+            .value_name("PIXELS")
+            .help("Repeat each sprite's edge pixels this far outward")
+            .default_value("0")
+        )
+        .arg(Arg::with_name("alpha_bleed")
+            .long("alpha-bleed")
+            .help("Fill the color of transparent pixels from nearby sprite pixels to avoid dark halos (not with --premultiply-alpha)")
+            .conflicts_with("premultiply_alpha")
+        )
+        .arg(Arg::with_name("premultiply_alpha")
+            .long("premultiply-alpha")
+            .help("Write atlas colors premultiplied by alpha")
+        )
+        .arg(Arg::with_name("pot")
+            .long("pot")
+            .help("Round atlas dimensions up to powers of two")
+        )
+        .arg(Arg::with_name("square")
+            .long("square")
+            .help("Make atlas pages square")
+        )
+        .arg(Arg::with_name("incremental")
+            .long("incremental")
+            .help("Keep sprites where the previous sprites.json placed them and only pack new or resized ones (tree packer only)")
+        )
+        .arg(Arg::with_name("force")
+            .long("force")
+            .help("Rebuild even if the inputs match the previous build")
         ).get_matches();
 
     let directories = opts.values_of("directories")
         .expect("One or more directories must be specified")
-        .map(|s| path::PathBuf::from(s))
+        .map(path::PathBuf::from)
         .collect::<Vec<_>>();
     let max_width = opts.value_of("max_width")
         .unwrap()
@@ -47,10 +179,61 @@ pub fn parse_ops() -> InputOptions {
     let max_height = opts.value_of("max_height")
         .unwrap()
         .parse::<u32>().expect("max_height must be a valid integer value");
+    let formats: Vec<ExportFormat> = opts.values_of("formats")
+        .map(|values| values.map(|s| s.parse::<ExportFormat>().unwrap()).collect())
+        .unwrap_or_default();
+    let css_prefix = opts.value_of("css_prefix").unwrap().to_string();
+    let heuristic = opts.value_of("heuristic").unwrap().parse::<MaxRectsHeuristic>().unwrap();
+    let packer = match opts.value_of("packer").unwrap().parse::<PackerKind>().unwrap() {
+        PackerKind::MaxRects(_) => PackerKind::MaxRects(heuristic),
+        kind => kind,
+    };
+    let allow_rotation = opts.is_present("rotate");
+    if allow_rotation {
+        if let Some(format) = formats.iter().find(|format| !format.supports_rotation()) {
+            clap::Error::with_description(
+                &format!("--rotate can't be used with the {} format", format.name()),
+                clap::ErrorKind::ArgumentConflict).exit();
+        }
+    }
+    let trim = opts.is_present("trim");
+    let trim_threshold = opts.value_of("trim_threshold")
+        .unwrap()
+        .parse::<u8>().expect("trim_threshold must be an integer between 0 and 255");
+    let padding = opts.value_of("padding")
+        .unwrap()
+        .parse::<u32>().expect("padding must be a valid integer value");
+    let border = opts.value_of("border")
+        .unwrap()
+        .parse::<u32>().expect("border must be a valid integer value");
+    let extrude = opts.value_of("extrude")
+        .unwrap()
+        .parse::<u32>().expect("extrude must be a valid integer value");
+    let alpha_bleed = opts.is_present("alpha_bleed");
+    let premultiply_alpha = opts.is_present("premultiply_alpha");
+    let power_of_two = opts.is_present("pot");
+    let square = opts.is_present("square");
+    let incremental = opts.is_present("incremental");
+    let force = opts.is_present("force");
 
     InputOptions {
         directories,
         max_width,
         max_height,
+        formats,
+        css_prefix,
+        packer,
+        allow_rotation,
+        trim,
+        trim_threshold,
+        padding,
+        border,
+        extrude,
+        alpha_bleed,
+        premultiply_alpha,
+        power_of_two,
+        square,
+        incremental,
+        force,
     }
 }
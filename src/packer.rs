@@ -0,0 +1,647 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::maxrects::{MaxRects, MaxRectsHeuristic};
+use crate::skyline::{Skyline, SkylineHeuristic};
+use crate::spatial_tree::{Region, Size, SpatialTree};
+
+/// Returned by [`Packer::insert`] when an item can't be placed. Hands the item back so the
+/// caller can try it elsewhere, e.g. on another atlas page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DoesNotFit<T> {
+    pub item: T,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A strategy for placing rectangular items into a 2D atlas.
+///
+/// Items are opaque to the packer; typically they are ids or handles into the caller's own
+/// sprite list. Rendering and metadata export only go through this trait, so any
+/// implementation can be used in place of the built-in packers.
+pub trait Packer<T> {
+    /// Places `item` with the given dimensions and returns the region it now occupies.
+    fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>>;
+
+    /// All placed items along with the region each occupies.
+    fn placements(&self) -> Vec<(&T, Region)>;
+
+    /// Dimensions of the atlas needed to hold every placed item.
+    fn size(&self) -> Size;
+
+    fn len(&self) -> usize {
+        self.placements().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The tree holding the layout, when this packer is or wraps a [`SpatialTree`]. Such a
+    /// layout can be saved and resumed later with [`PackerSettings::wrap_tree`].
+    fn spatial_tree(&self) -> Option<&SpatialTree<T>> {
+        None
+    }
+
+    /// Fraction of the atlas area covered by placed items.
+    fn occupancy(&self) -> f64 {
+        let size = self.size();
+        let total = u64::from(size.width) * u64::from(size.height);
+        if total == 0 {
+            return 0.0;
+        }
+        let used = self.placements().iter()
+            .map(|(_, region)| u64::from(region.width) * u64::from(region.height))
+            .sum::<u64>();
+        used as f64 / total as f64
+    }
+}
+
+/// Restrictions on the final atlas dimensions, for targets that need power-of-two or square
+/// textures.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SizeConstraints {
+    pub power_of_two: bool,
+    pub square: bool,
+}
+
+impl SizeConstraints {
+    pub fn is_constrained(&self) -> bool {
+        self.power_of_two || self.square
+    }
+
+    /// The smallest allowed size at least as large as `size`. An empty size stays empty.
+    pub fn apply(&self, size: &Size) -> Size {
+        if size.width == 0 && size.height == 0 {
+            return size.clone();
+        }
+        let round = |value: u32| if self.power_of_two {
+            value.max(1).checked_next_power_of_two().unwrap_or(u32::MAX)
+        } else {
+            value
+        };
+        let (width, height) = (round(size.width), round(size.height));
+        if self.square {
+            let side = width.max(height);
+            Size { width: side, height: side }
+        } else {
+            Size { width, height }
+        }
+    }
+
+    /// The largest allowed size that fits within `max`.
+    pub fn fit_within(&self, max: &Size) -> Size {
+        let round = |value: u32| if self.power_of_two && value > 0 {
+            1 << (31 - value.leading_zeros())
+        } else {
+            value
+        };
+        let (width, height) = (round(max.width), round(max.height));
+        if self.square {
+            let side = width.min(height);
+            Size { width: side, height: side }
+        } else {
+            Size { width, height }
+        }
+    }
+
+    /// Area of the atlas `size` turns into once the constraints are applied.
+    pub fn area(&self, size: &Size) -> u64 {
+        let size = self.apply(size);
+        u64::from(size.width) * u64::from(size.height)
+    }
+}
+
+/// [`SizeConstraints`] as seen by a packer whose bin differs from the final atlas by `margin`
+/// pixels in each dimension, from the border around the atlas and the padding after the last
+/// row and column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BinConstraints {
+    pub atlas: SizeConstraints,
+    pub margin: i64,
+}
+
+impl BinConstraints {
+    pub fn is_constrained(&self) -> bool {
+        self.atlas.is_constrained()
+    }
+
+    fn atlas_size(&self, bin: &Size) -> Size {
+        let side = |value: u32| (i64::from(value) + self.margin).clamp(1, i64::from(u32::MAX)) as u32;
+        Size { width: side(bin.width), height: side(bin.height) }
+    }
+
+    /// The smallest bin at least as large as `bin` whose atlas satisfies the constraints.
+    pub fn apply(&self, bin: &Size) -> Size {
+        if !self.is_constrained() || (bin.width == 0 && bin.height == 0) {
+            return bin.clone();
+        }
+        let atlas = self.atlas.apply(&self.atlas_size(bin));
+        let side = |value: u32, original: u32|
+            ((i64::from(value) - self.margin).clamp(0, i64::from(u32::MAX)) as u32).max(original);
+        Size { width: side(atlas.width, bin.width), height: side(atlas.height, bin.height) }
+    }
+
+    /// Area of the atlas a bin of `size` turns into once the constraints are applied.
+    pub fn area(&self, bin: &Size) -> u64 {
+        self.atlas.area(&self.atlas_size(bin))
+    }
+}
+
+/// Limits shared by the packers that grow their bin as items arrive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BinLimits {
+    /// Largest size the bin may grow to.
+    pub max_size: Size,
+    pub allow_rotation: bool,
+    /// Constraints the final atlas must satisfy. The bin only ever grows to allowed sizes.
+    pub constraints: BinConstraints,
+}
+
+impl Default for BinLimits {
+    fn default() -> BinLimits {
+        BinLimits::with_max_size(u32::MAX, u32::MAX)
+    }
+}
+
+impl BinLimits {
+    pub fn with_max_size(width: u32, height: u32) -> BinLimits {
+        BinLimits {
+            max_size: Size { width, height },
+            allow_rotation: false,
+            constraints: BinConstraints::default(),
+        }
+    }
+
+    /// The dimensions an item may be placed with: upright, then rotated if allowed.
+    pub(crate) fn orientations(&self, width: u32, height: u32) -> Vec<(u32, u32)> {
+        orientations(width, height, self.allow_rotation)
+    }
+
+    /// Bin sizes to try when an item of the given size doesn't fit in a bin of size `current`,
+    /// most preferred first. Growth goes in whichever direction keeps the bin closest to square
+    /// and is clamped to the maximum size. Under size constraints, candidates are rounded up to
+    /// allowed sizes and the ones wasting the least area come first.
+    pub(crate) fn growth_candidates(&self, current: &Size, width: u32, height: u32) -> Vec<Size> {
+        let max = &self.max_size;
+        let right = Size {
+            width: current.width.saturating_add(width).min(max.width),
+            height: current.height.max(height),
+        };
+        let down = Size {
+            width: current.width.max(width),
+            height: current.height.saturating_add(height).min(max.height),
+        };
+        let both = Size { width: right.width, height: down.height };
+        let mut candidates = if current.width + width < current.height + height {
+            vec![right, down, both]
+        } else {
+            vec![down, right, both]
+        };
+        candidates.retain(|candidate| candidate.width <= max.width && candidate.height <= max.height
+            && (candidate.width > current.width || candidate.height > current.height));
+        if self.constraints.is_constrained() {
+            candidates = candidates.iter()
+                .map(|candidate| self.constraints.apply(candidate))
+                .filter(|candidate| candidate.width <= max.width && candidate.height <= max.height)
+                .collect();
+            candidates.sort_by_key(|candidate| self.constraints.area(candidate));
+        }
+        candidates.dedup();
+        candidates
+    }
+}
+
+/// The dimensions an item may be placed with: upright, then rotated by 90 degrees if allowed.
+pub(crate) fn orientations(width: u32, height: u32, allow_rotation: bool) -> Vec<(u32, u32)> {
+    if allow_rotation && width != height {
+        vec![(width, height), (height, width)]
+    } else {
+        vec![(width, height)]
+    }
+}
+
+/// Whether an item `width` pixels wide was placed rotated by 90 degrees in `region`.
+pub fn is_rotated(region: &Region, width: u32) -> bool {
+    region.width != width
+}
+
+impl<T> fmt::Display for DoesNotFit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{} item does not fit in the atlas", self.width, self.height)
+    }
+}
+
+impl<T: fmt::Debug> Error for DoesNotFit<T> {}
+
+pub type BoxedPacker<T> = Box<dyn Packer<T>>;
+
+/// Constraints applied to every packer created from a [`PackerKind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackerSettings {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub allow_rotation: bool,
+    /// Empty pixels kept between neighbouring items.
+    pub padding: u32,
+    /// Empty pixels kept between the items and the edge of the atlas.
+    pub border: u32,
+    /// Pixels reserved around each item for copies of its edge pixels.
+    pub extrude: u32,
+    pub constraints: SizeConstraints,
+}
+
+impl Default for PackerSettings {
+    fn default() -> PackerSettings {
+        PackerSettings {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            allow_rotation: false,
+            padding: 0,
+            border: 0,
+            extrude: 0,
+            constraints: SizeConstraints::default(),
+        }
+    }
+}
+
+/// Wraps another packer to keep items apart. Each item is inserted with `extrude` extra pixels
+/// on every side plus `padding` to its right and bottom, and the whole layout is shifted in by
+/// `border` pixels. Placements report the item's own rectangle, without the extra space.
+pub struct Spaced<T> {
+    inner: BoxedPacker<T>,
+    padding: u32,
+    border: u32,
+    extrude: u32,
+}
+
+impl PackerSettings {
+    fn is_spaced(&self) -> bool {
+        self.padding > 0 || self.border > 0 || self.extrude > 0
+    }
+
+    fn bin_constraints(&self) -> BinConstraints {
+        let margin = if self.is_spaced() {
+            2*i64::from(self.border) - i64::from(self.padding)
+        } else {
+            0
+        };
+        BinConstraints { atlas: self.constraints, margin }
+    }
+
+    fn bin_limits(&self) -> BinLimits {
+        let max_size = self.bin_max_size();
+        BinLimits {
+            allow_rotation: self.allow_rotation,
+            constraints: self.bin_constraints(),
+            ..BinLimits::with_max_size(max_size.width, max_size.height)
+        }
+    }
+
+    /// Configures `tree` for these settings and wraps it like [`PackerKind::create`] does, so
+    /// a layout saved from an earlier run can take more items. The tree is expected to have
+    /// been packed with the same padding, border and extrusion.
+    pub fn wrap_tree<T: 'static>(&self, mut tree: SpatialTree<T>) -> BoxedPacker<T> {
+        let max_size = self.bin_max_size();
+        tree.set_max_size(max_size.width, max_size.height);
+        tree.set_allow_rotation(self.allow_rotation);
+        tree.set_size_constraints(self.bin_constraints());
+        self.wrap(Box::new(tree))
+    }
+
+    fn wrap<T: 'static>(&self, packer: BoxedPacker<T>) -> BoxedPacker<T> {
+        let packer: BoxedPacker<T> = if self.is_spaced() {
+            Box::new(Spaced::new(packer, self.padding, self.border, self.extrude))
+        } else {
+            packer
+        };
+        if self.constraints.is_constrained() {
+            Box::new(Constrained::new(packer, self.constraints))
+        } else {
+            packer
+        }
+    }
+
+    /// The largest atlas allowed by both the maximum size and the size constraints.
+    fn atlas_max_size(&self) -> Size {
+        self.constraints.fit_within(&Size { width: self.max_width, height: self.max_height })
+    }
+
+    /// The largest bin the underlying packer may use so the finished atlas, border included,
+    /// stays within the maximum size. The padding after the last row and column is never drawn,
+    /// so it doesn't count.
+    fn bin_max_size(&self) -> Size {
+        let max = self.atlas_max_size();
+        Size {
+            width: max.width.saturating_sub(2*self.border).saturating_add(self.padding),
+            height: max.height.saturating_sub(2*self.border).saturating_add(self.padding),
+        }
+    }
+}
+
+impl<T> Spaced<T> {
+    pub fn new(inner: BoxedPacker<T>, padding: u32, border: u32, extrude: u32) -> Spaced<T> {
+        Spaced { inner, padding, border, extrude }
+    }
+
+    fn item_region(&self, region: Region) -> Region {
+        let spacing = self.padding + 2*self.extrude;
+        Region::new(region.top + self.border + self.extrude, region.left + self.border + self.extrude,
+            region.width - spacing, region.height - spacing)
+    }
+}
+
+impl<T> Packer<T> for Spaced<T> {
+    fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>> {
+        let spacing = self.padding + 2*self.extrude;
+        match self.inner.insert(item, width + spacing, height + spacing) {
+            Ok(region) => Ok(self.item_region(region)),
+            Err(e) => Err(DoesNotFit { item: e.item, width, height }),
+        }
+    }
+
+    fn placements(&self) -> Vec<(&T, Region)> {
+        self.inner.placements().into_iter()
+            .map(|(item, region)| (item, self.item_region(region)))
+            .collect()
+    }
+
+    fn size(&self) -> Size {
+        let size = self.inner.size();
+        if self.inner.is_empty() {
+            return Size { width: 2*self.border, height: 2*self.border };
+        }
+        Size {
+            width: size.width.saturating_sub(self.padding) + 2*self.border,
+            height: size.height.saturating_sub(self.padding) + 2*self.border,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn spatial_tree(&self) -> Option<&SpatialTree<T>> {
+        self.inner.spatial_tree()
+    }
+}
+
+/// Wraps another packer, rounding its size up to satisfy [`SizeConstraints`].
+pub struct Constrained<T> {
+    inner: BoxedPacker<T>,
+    constraints: SizeConstraints,
+}
+
+impl<T> Constrained<T> {
+    pub fn new(inner: BoxedPacker<T>, constraints: SizeConstraints) -> Constrained<T> {
+        Constrained { inner, constraints }
+    }
+}
+
+impl<T> Packer<T> for Constrained<T> {
+    fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>> {
+        self.inner.insert(item, width, height)
+    }
+
+    fn placements(&self) -> Vec<(&T, Region)> {
+        self.inner.placements()
+    }
+
+    fn size(&self) -> Size {
+        self.constraints.apply(&self.inner.size())
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn spatial_tree(&self) -> Option<&SpatialTree<T>> {
+        self.inner.spatial_tree()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize)]
+pub enum PackerKind {
+    #[default]
+    SpatialTree,
+    MaxRects(MaxRectsHeuristic),
+    Skyline(SkylineHeuristic),
+}
+
+impl PackerKind {
+    pub const NAMES: &'static [&'static str] = &["tree", "maxrects", "skyline", "skyline-min-waste"];
+
+    pub fn create<T: 'static>(self, settings: &PackerSettings) -> BoxedPacker<T> {
+        let max_size = settings.bin_max_size();
+        let packer: BoxedPacker<T> = match self {
+            PackerKind::SpatialTree => {
+                let mut packer = SpatialTree::with_max_size(max_size.width, max_size.height);
+                packer.set_allow_rotation(settings.allow_rotation);
+                packer.set_size_constraints(settings.bin_constraints());
+                Box::new(packer)
+            },
+            PackerKind::MaxRects(heuristic) => Box::new(MaxRects::with_limits(heuristic, settings.bin_limits())),
+            PackerKind::Skyline(heuristic) => Box::new(Skyline::with_limits(heuristic, settings.bin_limits())),
+        };
+        settings.wrap(packer)
+    }
+
+    /// Like [`create`](PackerKind::create), for a page expected to hold items covering `area`
+    /// pixels once spaced. The bottom-left skyline wastes much less space filling a fixed width
+    /// than growing to stay square, so it starts as wide as a square a little larger than the
+    /// items and grows mostly downward.
+    fn create_page<T: 'static>(self, settings: &PackerSettings, area: u64) -> BoxedPacker<T> {
+        match self {
+            PackerKind::Skyline(SkylineHeuristic::BottomLeft) => {
+                let limits = settings.bin_limits();
+                let width = ((area as f64 * 1.1).sqrt() as u32).min(limits.max_size.width);
+                let packer = Skyline::with_initial_width(SkylineHeuristic::BottomLeft, width, limits);
+                settings.wrap(Box::new(packer))
+            },
+            kind => kind.create(settings),
+        }
+    }
+
+    /// Packs items in order onto as many pages as needed, each no larger than the configured
+    /// maximum size. Every item goes on the first page it fits on. Items too large for even an
+    /// empty page are handed back.
+    pub fn pack_pages<T: 'static, I>(self, items: I, settings: &PackerSettings)
+        -> (Vec<BoxedPacker<T>>, Vec<DoesNotFit<T>>)
+        where I: IntoIterator<Item = (T, u32, u32)>
+    {
+        let items = items.into_iter().collect::<Vec<_>>();
+        let spacing = if settings.is_spaced() { settings.padding + 2*settings.extrude } else { 0 };
+        let area = |width: u32, height: u32|
+            (u64::from(width) + u64::from(spacing)) * (u64::from(height) + u64::from(spacing));
+        let mut remaining = items.iter().map(|&(_, width, height)| area(width, height)).sum::<u64>();
+        let mut pages: Vec<BoxedPacker<T>> = Vec::new();
+        let mut rejected = Vec::new();
+        'items: for (item, width, height) in items {
+            let mut item = item;
+            for page in pages.iter_mut() {
+                match page.insert(item, width, height) {
+                    Ok(_) => {
+                        remaining -= area(width, height);
+                        continue 'items;
+                    },
+                    Err(e) => item = e.item,
+                }
+            }
+            let mut page = self.create_page(settings, remaining);
+            match page.insert(item, width, height) {
+                Ok(_) => pages.push(page),
+                Err(e) => rejected.push(e),
+            }
+            remaining -= area(width, height);
+        }
+        (pages, rejected)
+    }
+
+    /// Packs items of the given sizes, in order, into a single page with no size limit and
+    /// returns the resulting packer.
+    pub fn pack_sizes(self, sizes: &[(u32, u32)], settings: &PackerSettings) -> BoxedPacker<usize> {
+        let settings = PackerSettings { max_width: u32::MAX, max_height: u32::MAX, ..*settings };
+        let mut packer = self.create(&settings);
+        for (i, &(width, height)) in sizes.iter().enumerate() {
+            let _ = packer.insert(i, width, height);
+        }
+        packer
+    }
+}
+
+impl FromStr for PackerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<PackerKind, String> {
+        match s {
+            "tree" => Ok(PackerKind::SpatialTree),
+            "maxrects" => Ok(PackerKind::MaxRects(MaxRectsHeuristic::default())),
+            "skyline" => Ok(PackerKind::Skyline(SkylineHeuristic::BottomLeft)),
+            "skyline-min-waste" => Ok(PackerKind::Skyline(SkylineHeuristic::MinWaste)),
+            _ => Err(format!("Unknown packer '{}'", s)),
+        }
+    }
+}
+
+impl<T> Packer<T> for SpatialTree<T> {
+    fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>> {
+        SpatialTree::insert(self, item, width, height)
+    }
+
+    fn len(&self) -> usize {
+        self.size()
+    }
+
+    fn placements(&self) -> Vec<(&T, Region)> {
+        SpatialTree::placements(self).collect()
+    }
+
+    fn size(&self) -> Size {
+        Size { width: self.region().width, height: self.region().height }
+    }
+
+    fn spatial_tree(&self) -> Option<&SpatialTree<T>> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POT: SizeConstraints = SizeConstraints { power_of_two: true, square: false };
+    const SQUARE: SizeConstraints = SizeConstraints { power_of_two: false, square: true };
+    const BOTH: SizeConstraints = SizeConstraints { power_of_two: true, square: true };
+
+    fn size(width: u32, height: u32) -> Size {
+        Size { width, height }
+    }
+
+    #[test]
+    fn apply_rounds_up_to_an_allowed_size() {
+        assert_eq!(SizeConstraints::default().apply(&size(100, 30)), size(100, 30));
+        assert_eq!(POT.apply(&size(100, 30)), size(128, 32));
+        assert_eq!(POT.apply(&size(64, 1)), size(64, 1));
+        assert_eq!(SQUARE.apply(&size(100, 30)), size(100, 100));
+        assert_eq!(BOTH.apply(&size(100, 30)), size(128, 128));
+        assert_eq!(BOTH.apply(&size(0, 0)), size(0, 0));
+        assert_eq!(POT.apply(&size(u32::MAX, 3)), size(u32::MAX, 4));
+    }
+
+    #[test]
+    fn fit_within_rounds_down_to_an_allowed_size() {
+        assert_eq!(SizeConstraints::default().fit_within(&size(100, 30)), size(100, 30));
+        assert_eq!(POT.fit_within(&size(100, 30)), size(64, 16));
+        assert_eq!(POT.fit_within(&size(64, 0)), size(64, 0));
+        assert_eq!(SQUARE.fit_within(&size(100, 30)), size(30, 30));
+        assert_eq!(BOTH.fit_within(&size(100, 30)), size(16, 16));
+        assert_eq!(BOTH.fit_within(&size(u32::MAX, u32::MAX)), size(1 << 31, 1 << 31));
+    }
+
+    const KINDS: [PackerKind; 4] = [
+        PackerKind::SpatialTree,
+        PackerKind::MaxRects(MaxRectsHeuristic::BestShortSideFit),
+        PackerKind::Skyline(SkylineHeuristic::BottomLeft),
+        PackerKind::Skyline(SkylineHeuristic::MinWaste),
+    ];
+
+    #[test]
+    fn pack_pages_spills_onto_new_pages_within_the_maximum_size() {
+        let settings = PackerSettings { max_width: 100, max_height: 100, ..PackerSettings::default() };
+        for &kind in &KINDS {
+            let items = (0..40).map(|i| (i, 30, 20)).chain(Some((40, 101, 10)));
+            let (pages, rejected) = kind.pack_pages(items, &settings);
+            assert_eq!(rejected.iter().map(|e| e.item).collect::<Vec<_>>(), vec![40], "{:?}", kind);
+            assert!(pages.len() > 1, "{:?}", kind);
+            let mut packed = Vec::new();
+            for page in &pages {
+                let size = page.size();
+                assert!(size.width <= 100 && size.height <= 100, "{:?}: {:?}", kind, size);
+                let placements = page.placements();
+                for (i, (&item, a)) in placements.iter().enumerate() {
+                    assert!(Region::new(0, 0, size.width, size.height).contains(a), "{:?}: {:?}", kind, a);
+                    for (_, b) in &placements[i + 1..] {
+                        assert!(!a.intersects(b), "{:?}: {:?} overlaps {:?}", kind, a, b);
+                    }
+                    packed.push(item);
+                }
+            }
+            packed.sort_unstable();
+            assert_eq!(packed, (0..40).collect::<Vec<_>>(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn spaced_items_keep_their_padding_border_and_extrusion() {
+        let (padding, border, extrude) = (2, 3, 1);
+        let settings = PackerSettings { padding, border, extrude, ..PackerSettings::default() };
+        for &kind in &KINDS {
+            let mut single = kind.create(&settings);
+            assert_eq!(single.size(), size(2*border, 2*border));
+            assert_eq!(single.insert(0, 10, 6).unwrap(), Region::new(border + extrude, border + extrude, 10, 6));
+            assert_eq!(single.size(), size(10 + 2*(border + extrude), 6 + 2*(border + extrude)));
+
+            let mut packer = kind.create(&settings);
+            for i in 0..30 {
+                let region = packer.insert(i, 5 + i % 7, 4 + i % 5).unwrap();
+                assert_eq!((region.width, region.height), (5 + i % 7, 4 + i % 5));
+            }
+            let size = packer.size();
+            let inside = Region::new(border, border, size.width - 2*border, size.height - 2*border);
+            let placements = packer.placements();
+            let extruded = |region: &Region, extra: u32| Region::new(region.top - extrude, region.left - extrude,
+                region.width + 2*extrude + extra, region.height + 2*extrude + extra);
+            for (i, (_, a)) in placements.iter().enumerate() {
+                assert!(inside.contains(&extruded(a, 0)), "{:?}: {:?} reaches into the border", kind, a);
+                for (_, b) in &placements[i + 1..] {
+                    assert!(!extruded(a, padding).intersects(&extruded(b, 0))
+                        && !extruded(b, padding).intersects(&extruded(a, 0)),
+                        "{:?}: {:?} is too close to {:?}", kind, a, b);
+                }
+            }
+        }
+    }
+}
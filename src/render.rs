@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use image;
+use image::{self, GenericImageView};
+use crate::input::Sprite;
+use crate::packer::{is_rotated, Packer};
 use crate::spatial_tree::{Region, SpatialTree};
 
 #[repr(C)]
@@ -18,7 +21,21 @@ impl Rgba {
     }
 }
 
-static COLORS: &'static [Rgba] = &[
+/// Options controlling how sprites are drawn into the atlas images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct RenderSettings {
+    /// Pixels each sprite's edge is repeated outward; must match the packer's extrusion.
+    pub extrude: u32,
+    /// Fill the color of fully transparent pixels from the nearest visible ones. Has no effect
+    /// together with `premultiply_alpha`, which turns those pixels black again.
+    pub alpha_bleed: bool,
+    /// Write color channels multiplied by alpha.
+    pub premultiply_alpha: bool,
+}
+
+const TRANSPARENT: Rgba = Rgba::new(0, 0, 0, 0);
+
+static COLORS: &[Rgba] = &[
     Rgba::new(255, 0, 0, 255),
     Rgba::new(0, 255, 0, 255),
     Rgba::new(0, 0, 255, 255),
@@ -32,65 +49,301 @@ static COLORS: &'static [Rgba] = &[
     Rgba::new(127, 0, 127, 255),
 ];
 
+/// Outlines `region` with a one pixel line drawn on its outermost pixels, so nothing outside
+/// the region is touched.
 fn draw_border(color: Rgba, region: &Region, width: u32, pixels: &mut [Rgba]) {
-    for x in (region.left..region.left+region.width) {
-        unsafe { *pixels.get_unchecked_mut((x + width*region.top) as usize) = color; }
-        unsafe { *pixels.get_unchecked_mut((x + width*region.bottom()) as usize) = color; }
+    if region.width == 0 || region.height == 0 {
+        return;
+    }
+    for x in region.left..region.right() {
+        pixels[(x + width*region.top) as usize] = color;
+        pixels[(x + width*(region.bottom() - 1)) as usize] = color;
     }
-    for y in (region.top..region.top+region.height) {
-        unsafe { *pixels.get_unchecked_mut((region.left + width*y) as usize) = color; }
-        unsafe { *pixels.get_unchecked_mut((region.right() + width*y) as usize) = color; }
+    for y in region.top..region.bottom() {
+        pixels[(region.left + width*y) as usize] = color;
+        pixels[(region.right() - 1 + width*y) as usize] = color;
     }
 }
 
+/// The part of `region`, grown by `margin` on every side, that lies on a `width` pixel wide
+/// image stored in `pixels`, as ranges of columns and rows.
+fn clip(region: &Region, margin: u32, width: u32, pixels: &[Rgba])
+    -> (std::ops::Range<u32>, std::ops::Range<u32>)
+{
+    let height = if width == 0 { 0 } else { (pixels.len() / width as usize) as u32 };
+    let columns = region.left.saturating_sub(margin)
+        ..region.left.saturating_add(region.width).saturating_add(margin).min(width);
+    let rows = region.top.saturating_sub(margin)
+        ..region.top.saturating_add(region.height).saturating_add(margin).min(height);
+    (columns, rows)
+}
+
 fn draw_rectangle(color: Rgba, region: &Region, width: u32, pixels: &mut [Rgba]) {
-    for y in (region.top..region.top+region.height) {
-        for x in (region.left..region.left+region.width) {
-            unsafe { *pixels.get_unchecked_mut((x + y*width) as usize) = color; }
+    let (columns, rows) = clip(region, 0, width, pixels);
+    for y in rows {
+        for x in columns.clone() {
+            pixels[(x + y*width) as usize] = color;
         }
     }
 }
 
-fn blit_sprite(sprite: &image::RgbaImage, region: &Region, width: u32, pixels: &mut [Rgba]) {
-    for (image_y, sprite_y) in (region.top..region.top+region.height).zip(0..region.height) {
-        for (image_x, sprite_x) in (region.left..region.left + region.width).zip(0..region.width) {
-            let pixel = sprite.get_pixel(sprite_x, sprite_y);
+/// Copies `sprite` into `region`, repeating its outermost rows and columns `extrude` pixels
+/// outward. When the region's width doesn't match the sprite, the sprite was placed rotated and
+/// is written turned 90 degrees clockwise. Whatever falls outside the image is left out.
+fn blit_sprite(sprite: &image::RgbaImage, region: &Region, extrude: u32, width: u32,
+    pixels: &mut [Rgba])
+{
+    if region.width == 0 || region.height == 0 {
+        return;
+    }
+    let rotated = is_rotated(region, sprite.width());
+    let (columns, rows) = clip(region, extrude, width, pixels);
+    let last_x = region.width - 1;
+    let last_y = region.height - 1;
+    for image_y in rows {
+        let sprite_y = image_y.saturating_sub(region.top).min(last_y);
+        for image_x in columns.clone() {
+            let sprite_x = image_x.saturating_sub(region.left).min(last_x);
+            let pixel = if rotated {
+                sprite.get_pixel(sprite_y, sprite.height() - 1 - sprite_x)
+            } else {
+                sprite.get_pixel(sprite_x, sprite_y)
+            };
             let color = Rgba::new(pixel.data[0], pixel.data[1], pixel.data[2], pixel.data[3]);
-            unsafe { *pixels.get_unchecked_mut((image_x + image_y*width) as usize) = color; }
+            pixels[(image_x + image_y*width) as usize] = color;
         }
     }
 }
 
-pub fn draw_spatial_tree_nodes<T>(tree: &mut SpatialTree<T>, path: &Path) {
+/// Gives every fully transparent pixel the average color of its nearest non-transparent
+/// neighbours, spreading outward one ring at a time, so filtering and scaling don't pull in
+/// black from transparent areas. Alpha is left unchanged.
+fn bleed_alpha(pixels: &mut [Rgba], width: u32, height: u32) {
+    let (width, height) = (width as usize, height as usize);
+    let mut filled = pixels[..width*height].iter().map(|pixel| pixel.a > 0).collect::<Vec<_>>();
+    let mut queued = filled.clone();
+    let neighbours = |index: usize| {
+        let (x, y) = (index % width, index / width);
+        let mut result = Vec::with_capacity(8);
+        for ny in y.saturating_sub(1)..(y + 2).min(height) {
+            for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                if (nx, ny) != (x, y) {
+                    result.push(nx + ny*width);
+                }
+            }
+        }
+        result
+    };
+
+    let mut frontier = Vec::new();
+    for index in 0..width*height {
+        if !filled[index] && neighbours(index).iter().any(|&n| filled[n]) {
+            queued[index] = true;
+            frontier.push(index);
+        }
+    }
+    while !frontier.is_empty() {
+        let colors = frontier.iter()
+            .map(|&index| {
+                let (mut r, mut g, mut b, mut count) = (0u32, 0u32, 0u32, 0u32);
+                for n in neighbours(index).into_iter().filter(|&n| filled[n]) {
+                    r += u32::from(pixels[n].r);
+                    g += u32::from(pixels[n].g);
+                    b += u32::from(pixels[n].b);
+                    count += 1;
+                }
+                ((r / count) as u8, (g / count) as u8, (b / count) as u8)
+            })
+            .collect::<Vec<_>>();
+        for (&index, &(r, g, b)) in frontier.iter().zip(&colors) {
+            pixels[index] = Rgba::new(r, g, b, pixels[index].a);
+            filled[index] = true;
+        }
+
+        let mut next = Vec::new();
+        for &index in &frontier {
+            for n in neighbours(index) {
+                if !queued[n] {
+                    queued[n] = true;
+                    next.push(n);
+                }
+            }
+        }
+        frontier = next;
+    }
+}
+
+fn premultiply_alpha(pixels: &mut [Rgba]) {
+    let multiply = |channel: u8, alpha: u8| ((u32::from(channel) * u32::from(alpha) + 127) / 255) as u8;
+    for pixel in pixels.iter_mut() {
+        *pixel = Rgba::new(multiply(pixel.r, pixel.a), multiply(pixel.g, pixel.a),
+            multiply(pixel.b, pixel.a), pixel.a);
+    }
+}
+
+/// Applies the whole-atlas passes selected in `settings` and writes the image. Premultiplying
+/// runs last, so it zeroes the color bleeding gave to fully transparent pixels.
+fn finish_atlas(pixels: &mut [Rgba], width: u32, height: u32, settings: &RenderSettings, path: &Path) {
+    if settings.alpha_bleed {
+        bleed_alpha(pixels, width, height);
+    }
+    if settings.premultiply_alpha {
+        premultiply_alpha(pixels);
+    }
+    save_pixels(pixels, width, height, path);
+}
+
+fn save_pixels(pixels: &[Rgba], width: u32, height: u32, path: &Path) {
+    let mut u8_pixels = Vec::with_capacity((width*height*4) as usize);
+    for color in &pixels[..(width*height) as usize] {
+        u8_pixels.extend_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+    let img = image::RgbaImage::from_raw(width, height, u8_pixels).unwrap();
+    img.save(path).unwrap();
+}
+
+pub fn draw_spatial_tree_nodes<T>(tree: &SpatialTree<T>, path: &Path) {
     let region = tree.region().clone();
     let mut pixels = vec![Rgba::new(255, 255, 255, 255); region.area() as usize];
     let mut i = 0;
     for node in tree.iter_nodes() {
-        if let Some(v) = &node.value {
-            let inner_region = Region::new(node.region.top, node.region.left, node.value_size.0, node.value_size.1);
+        if node.value.is_some() {
+            let inner_region = node.value_region();
             draw_rectangle(COLORS[i % COLORS.len()], &inner_region, region.width, &mut pixels);
+            draw_border(Rgba::new(255, 0, 255, 255), node.region(), region.width, &mut pixels);
             i += 1;
         }
     }
 
-    let u8_pixels = unsafe { Vec::from_raw_parts(std::mem::transmute::<_, *mut u8>(pixels.as_mut_ptr()), (region.area()*4) as usize, (region.area()*4) as usize) };
-    let img = image::RgbaImage::from_raw(tree.region().width, tree.region().height, u8_pixels).unwrap();
-    std::mem::forget(pixels);
-    img.save(path).unwrap();
+    save_pixels(&pixels, region.width, region.height, path);
 }
 
-pub fn draw_spatial_tree_sprites(tree: &mut SpatialTree<(PathBuf, image::RgbaImage)>, path: &Path) {
-    let region = tree.region().clone();
-    let mut pixels = vec![Rgba::new(255, 255, 255, 255); ((region.width+1)*(region.height+1)) as usize];
-    for node in tree.iter_nodes() {
-        if let Some(v) = &node.value {
-            let inner_region = Region::new(node.region.top, node.region.left, node.value_size.0, node.value_size.1);
-            blit_sprite(&v.1, &inner_region, region.width, &mut pixels);
+pub fn draw_sprites(packer: &dyn Packer<Sprite>, settings: &RenderSettings, path: &Path) {
+    let size = packer.size();
+    let mut pixels = vec![TRANSPARENT; (size.width*size.height) as usize];
+    for (sprite, region) in packer.placements() {
+        blit_sprite(&sprite.image, &region, settings.extrude, size.width, &mut pixels);
+    }
+    finish_atlas(&mut pixels, size.width, size.height, settings, path);
+}
+
+/// Draws `packer`'s layout at `scale` times the size, taking each sprite from its high-DPI
+/// variant when there is one (trimmed to match the base sprite) and upscaling it otherwise.
+pub fn draw_hidpi_sprites(packer: &dyn Packer<Sprite>,
+    variants: &HashMap<PathBuf, image::RgbaImage>, scale: u32, settings: &RenderSettings, path: &Path)
+{
+    let extrude = settings.extrude*scale;
+    let size = packer.size();
+    let width = size.width*scale;
+    let height = size.height*scale;
+    let mut pixels = vec![TRANSPARENT; (width*height) as usize];
+    for (sprite, region) in packer.placements() {
+        let scaled_region = Region::new(region.top*scale, region.left*scale,
+            region.width*scale, region.height*scale);
+        let (sprite_width, sprite_height) = if is_rotated(&region, sprite.image.width()) {
+            (scaled_region.height, scaled_region.width)
+        } else {
+            (scaled_region.width, scaled_region.height)
+        };
+        let variant = variants.get(&sprite.path).filter(|variant| variant.dimensions() ==
+            (sprite.source_size.width*scale, sprite.source_size.height*scale));
+        if let Some(variant) = variant {
+            let trimmed = variant.view(sprite.offset.0*scale, sprite.offset.1*scale,
+                sprite_width, sprite_height).to_image();
+            blit_sprite(&trimmed, &scaled_region, extrude, width, &mut pixels);
+        } else {
+            let resized = image::imageops::resize(&sprite.image, sprite_width, sprite_height,
+                image::FilterType::Nearest);
+            blit_sprite(&resized, &scaled_region, extrude, width, &mut pixels);
         }
-        draw_border(Rgba::new(255, 0, 255, 255), &node.region, region.width, &mut pixels);
     }
-    let u8_pixels = unsafe { Vec::from_raw_parts(std::mem::transmute::<_, *mut u8>(pixels.as_mut_ptr()), (region.area()*4) as usize, (region.area()*4) as usize) };
-    let img = image::RgbaImage::from_raw(tree.region().width, tree.region().height, u8_pixels).unwrap();
-    std::mem::forget(pixels);
-    img.save(path).unwrap();
-}
\ No newline at end of file
+    finish_atlas(&mut pixels, width, height, settings, path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sprite whose red and green channels hold each pixel's coordinates.
+    fn gradient(width: u32, height: u32) -> image::RgbaImage {
+        image::RgbaImage::from_fn(width, height, |x, y| image::Rgba([x as u8, y as u8, 0, 255]))
+    }
+
+    fn at(x: u32, y: u32) -> Rgba {
+        Rgba::new(x as u8, y as u8, 0, 255)
+    }
+
+    #[test]
+    fn blit_sprite_repeats_the_edges_outward() {
+        let (width, height) = (6, 7);
+        let mut pixels = vec![TRANSPARENT; (width*height) as usize];
+        blit_sprite(&gradient(2, 3), &Region::new(2, 2, 2, 3), 1, width, &mut pixels);
+        for y in 0..height {
+            for x in 0..width {
+                let expected = if (1..5).contains(&x) && (1..6).contains(&y) {
+                    at(x.clamp(2, 3) - 2, y.clamp(2, 4) - 2)
+                } else {
+                    TRANSPARENT
+                };
+                assert_eq!(pixels[(x + y*width) as usize], expected, "at {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn blit_sprite_turns_rotated_sprites_clockwise() {
+        let (width, height) = (5, 4);
+        let mut pixels = vec![TRANSPARENT; (width*height) as usize];
+        blit_sprite(&gradient(2, 3), &Region::new(1, 1, 3, 2), 1, width, &mut pixels);
+        let rows = [
+            [at(0, 2), at(0, 2), at(0, 1), at(0, 0), at(0, 0)],
+            [at(0, 2), at(0, 2), at(0, 1), at(0, 0), at(0, 0)],
+            [at(1, 2), at(1, 2), at(1, 1), at(1, 0), at(1, 0)],
+            [at(1, 2), at(1, 2), at(1, 1), at(1, 0), at(1, 0)],
+        ];
+        assert_eq!(pixels, rows.concat());
+    }
+
+    #[test]
+    fn blit_sprite_leaves_out_extrusion_beyond_the_image() {
+        let mut pixels = vec![TRANSPARENT; 4];
+        blit_sprite(&gradient(2, 2), &Region::new(0, 0, 2, 2), 3, 2, &mut pixels);
+        assert_eq!(pixels, vec![at(0, 0), at(1, 0), at(0, 1), at(1, 1)]);
+    }
+
+    #[test]
+    fn bleed_alpha_spreads_the_nearest_colors_into_transparent_pixels() {
+        let mut pixels = vec![
+            Rgba::new(200, 0, 0, 255), TRANSPARENT, TRANSPARENT, TRANSPARENT, Rgba::new(0, 0, 100, 1),
+            TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT, TRANSPARENT,
+        ];
+        bleed_alpha(&mut pixels, 5, 2);
+        assert_eq!(pixels, vec![
+            Rgba::new(200, 0, 0, 255), Rgba::new(200, 0, 0, 0), Rgba::new(100, 0, 50, 0),
+            Rgba::new(0, 0, 100, 0), Rgba::new(0, 0, 100, 1),
+            Rgba::new(200, 0, 0, 0), Rgba::new(200, 0, 0, 0), Rgba::new(100, 0, 50, 0),
+            Rgba::new(0, 0, 100, 0), Rgba::new(0, 0, 100, 0),
+        ]);
+
+        let mut empty = vec![TRANSPARENT; 6];
+        bleed_alpha(&mut empty, 3, 2);
+        assert_eq!(empty, vec![TRANSPARENT; 6]);
+    }
+
+    #[test]
+    fn premultiply_alpha_scales_colors_by_alpha_with_rounding() {
+        let mut pixels = vec![
+            Rgba::new(255, 100, 1, 255),
+            Rgba::new(255, 100, 1, 128),
+            Rgba::new(255, 100, 3, 64),
+            Rgba::new(255, 100, 1, 0),
+        ];
+        premultiply_alpha(&mut pixels);
+        assert_eq!(pixels, vec![
+            Rgba::new(255, 100, 1, 255),
+            Rgba::new(128, 50, 1, 128),
+            Rgba::new(64, 25, 1, 64),
+            TRANSPARENT,
+        ]);
+    }
+}
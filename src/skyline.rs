@@ -0,0 +1,324 @@
+use serde::Serialize;
+
+use crate::packer::{BinLimits, DoesNotFit, Packer};
+use crate::spatial_tree::{Region, Size};
+
+/// Rule used to choose where along the skyline an item is placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize)]
+pub enum SkylineHeuristic {
+    /// Place each item as low as possible, preferring the leftmost position.
+    #[default]
+    BottomLeft,
+    /// Place each item where it leaves the least unusable area underneath, and reuse those
+    /// areas for later items.
+    MinWaste,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SkylineSegment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+/// Skyline bin packer. Tracks only the upper outline of the placed items, which makes each
+/// insertion proportional to the length of the outline rather than the number of items.
+#[derive(Clone, Debug)]
+pub struct Skyline<T> {
+    heuristic: SkylineHeuristic,
+    width: u32,
+    height: u32,
+    limits: BinLimits,
+    skyline: Vec<SkylineSegment>,
+    waste: Vec<Region>,
+    used: Vec<(T, Region)>,
+}
+
+impl SkylineSegment {
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+}
+
+impl<T> Skyline<T> {
+    pub fn new(heuristic: SkylineHeuristic) -> Skyline<T> {
+        Skyline::with_initial_size(heuristic, 0, 0)
+    }
+
+    pub fn with_initial_size(heuristic: SkylineHeuristic, width: u32, height: u32) -> Skyline<T> {
+        let skyline = if width > 0 {
+            vec![SkylineSegment { x: 0, y: 0, width }]
+        } else {
+            Vec::new()
+        };
+        Skyline {
+            heuristic,
+            width,
+            height,
+            limits: BinLimits::default(),
+            skyline,
+            waste: Vec::new(),
+            used: Vec::new(),
+        }
+    }
+
+    pub fn with_limits(heuristic: SkylineHeuristic, limits: BinLimits) -> Skyline<T> {
+        Skyline { limits, ..Skyline::new(heuristic) }
+    }
+
+    /// An empty bin `width` wide, which grows downward until it's about as tall as it's wide.
+    pub fn with_initial_width(heuristic: SkylineHeuristic, width: u32, limits: BinLimits) -> Skyline<T> {
+        Skyline { limits, ..Skyline::with_initial_size(heuristic, width, 0) }
+    }
+
+    pub fn limits(&self) -> &BinLimits {
+        &self.limits
+    }
+
+    pub fn heuristic(&self) -> SkylineHeuristic {
+        self.heuristic
+    }
+
+    /// Returns the lowest y at which an item of the given size can rest when its left edge is
+    /// aligned with segment `index`.
+    fn fit(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.skyline[index].x;
+        if x + width > self.width {
+            return None;
+        }
+        let mut y = 0;
+        for segment in self.skyline[index..].iter().take_while(|segment| segment.x < x + width) {
+            y = y.max(segment.y);
+            if y + height > self.height {
+                return None;
+            }
+        }
+        Some(y)
+    }
+
+    /// Area left unreachable beneath an item of the given width resting at `y` on segment `index`.
+    fn wasted_area(&self, index: usize, width: u32, y: u32) -> u64 {
+        let left = self.skyline[index].x;
+        let right = left + width;
+        self.skyline[index..].iter()
+            .take_while(|segment| segment.x < right)
+            .map(|segment| u64::from(segment.right().min(right) - segment.x.max(left))
+                * u64::from(y - segment.y))
+            .sum()
+    }
+
+    fn find_skyline_position(&self, width: u32, height: u32) -> Option<(usize, Region)> {
+        let mut best: Option<((u64, u64), usize, Region)> = None;
+        for (width, height) in self.limits.orientations(width, height) {
+            for index in 0..self.skyline.len() {
+                if let Some(y) = self.fit(index, width, height) {
+                    let score = match self.heuristic {
+                        SkylineHeuristic::BottomLeft =>
+                            (u64::from(y + height), u64::from(self.skyline[index].width)),
+                        SkylineHeuristic::MinWaste =>
+                            (self.wasted_area(index, width, y), u64::from(y + height)),
+                    };
+                    if best.as_ref().is_none_or(|(best_score, _, _)| score < *best_score) {
+                        best = Some((score, index, Region::new(y, self.skyline[index].x, width, height)));
+                    }
+                }
+            }
+        }
+        best.map(|(_, index, region)| (index, region))
+    }
+
+    /// Finds the smallest waste rectangle that can hold the item, returning its index and the
+    /// dimensions the item should be placed with.
+    fn find_waste_position(&self, width: u32, height: u32) -> Option<(usize, u32, u32)> {
+        self.limits.orientations(width, height).into_iter()
+            .flat_map(|(width, height)| self.waste.iter()
+                .enumerate()
+                .filter(move |(_, free)| free.width >= width && free.height >= height)
+                .map(move |(index, free)| (u64::from(free.width) * u64::from(free.height), index, width, height)))
+            .min_by_key(|(area, _, _, _)| *area)
+            .map(|(_, index, width, height)| (index, width, height))
+    }
+
+    /// Places an item in the top left of waste rectangle `index`, splitting the remainder
+    /// along the shorter leftover axis.
+    fn place_in_waste(&mut self, index: usize, width: u32, height: u32) -> Region {
+        let free = self.waste.swap_remove(index);
+        let placed = Region::new(free.top, free.left, width, height);
+        let (right, bottom) = if free.width - width > free.height - height {
+            (Region::new(free.top, free.left + width, free.width - width, free.height),
+                Region::new(free.top + height, free.left, width, free.height - height))
+        } else {
+            (Region::new(free.top, free.left + width, free.width - width, height),
+                Region::new(free.top + height, free.left, free.width, free.height - height))
+        };
+        for remainder in [right, bottom].iter() {
+            if remainder.width > 0 && remainder.height > 0 {
+                self.waste.push(remainder.clone());
+            }
+        }
+        placed
+    }
+
+    fn record_waste(&mut self, index: usize, placed: &Region) {
+        for segment in self.skyline[index..].iter().take_while(|segment| segment.x < placed.right()) {
+            if segment.y < placed.top {
+                let left = segment.x.max(placed.left);
+                let right = segment.right().min(placed.right());
+                self.waste.push(Region::new(segment.y, left, right - left, placed.top - segment.y));
+            }
+        }
+    }
+
+    fn add_level(&mut self, index: usize, placed: &Region) {
+        if self.heuristic == SkylineHeuristic::MinWaste {
+            self.record_waste(index, placed);
+        }
+
+        self.skyline.insert(index, SkylineSegment {
+            x: placed.left,
+            y: placed.bottom(),
+            width: placed.width,
+        });
+        let right = placed.right();
+        while index + 1 < self.skyline.len() && self.skyline[index + 1].x < right {
+            let next = &mut self.skyline[index + 1];
+            if next.right() <= right {
+                self.skyline.remove(index + 1);
+            } else {
+                next.width -= right - next.x;
+                next.x = right;
+                break;
+            }
+        }
+
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Extends the bin to the first growth candidate the item fits on the skyline of, adding
+    /// new width as a segment at the bottom, and returns where the item can go.
+    fn grow(&mut self, width: u32, height: u32) -> Option<(usize, Region)> {
+        let current = Size { width: self.width, height: self.height };
+        for candidate in self.limits.growth_candidates(&current, width, height) {
+            if candidate.width > self.width {
+                self.skyline.push(SkylineSegment { x: self.width, y: 0, width: candidate.width - self.width });
+            }
+            self.width = candidate.width;
+            self.height = candidate.height;
+            if let Some(position) = self.find_skyline_position(width, height) {
+                return Some(position);
+            }
+            if candidate.width > current.width {
+                self.skyline.pop();
+            }
+            self.width = current.width;
+            self.height = current.height;
+        }
+        None
+    }
+}
+
+impl<T> Packer<T> for Skyline<T> {
+    fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>> {
+        let region = match self.find_waste_position(width, height) {
+            Some((index, placed_width, placed_height)) => self.place_in_waste(index, placed_width, placed_height),
+            None => {
+                let (index, region) = match self.find_skyline_position(width, height) {
+                    Some(position) => position,
+                    None => match self.grow(width, height) {
+                        Some(position) => position,
+                        None => return Err(DoesNotFit { item, width, height }),
+                    },
+                };
+                self.add_level(index, &region);
+                region
+            },
+        };
+        self.used.push((item, region.clone()));
+        Ok(region)
+    }
+
+    fn len(&self) -> usize {
+        self.used.len()
+    }
+
+    fn placements(&self) -> Vec<(&T, Region)> {
+        self.used.iter()
+            .map(|(item, region)| (item, region.clone()))
+            .collect()
+    }
+
+    fn size(&self) -> Size {
+        Size { width: self.width, height: self.height }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Item sizes from a fixed linear congruential sequence.
+    fn sizes(count: usize, seed: u64) -> Vec<(u32, u32)> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            1 + ((state >> 33) % 40) as u32
+        };
+        (0..count).map(|_| (next(), next())).collect()
+    }
+
+    fn assert_packed(packer: &Skyline<usize>, count: usize) {
+        let size = packer.size();
+        let bin = Region::new(0, 0, size.width, size.height);
+        let placements = packer.placements();
+        assert_eq!(placements.len(), count);
+        for (i, (_, a)) in placements.iter().enumerate() {
+            assert!(bin.contains(a), "{:?} is outside the {}x{} bin", a, size.width, size.height);
+            for (_, b) in &placements[i + 1..] {
+                assert!(!a.intersects(b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn placements_stay_inside_the_bin_without_overlapping() {
+        for &heuristic in &[SkylineHeuristic::BottomLeft, SkylineHeuristic::MinWaste] {
+            for &allow_rotation in &[false, true] {
+                for &width in &[0, 150] {
+                    let limits = BinLimits { allow_rotation, ..BinLimits::default() };
+                    let mut packer = Skyline::with_initial_width(heuristic, width, limits);
+                    for (i, &(width, height)) in sizes(120, u64::from(allow_rotation)).iter().enumerate() {
+                        packer.insert(i, width, height).unwrap();
+                    }
+                    assert_packed(&packer, 120);
+                    assert!(packer.size().width >= width);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_fixed_width_packs_tighter_than_growing() {
+        let mut sizes = sizes(500, 4);
+        sizes.sort_by_key(|&(width, height)| std::cmp::Reverse(width.max(height)));
+        let area = sizes.iter().map(|&(width, height)| u64::from(width) * u64::from(height)).sum::<u64>();
+        let occupancy = |mut packer: Skyline<usize>| {
+            for (i, &(width, height)) in sizes.iter().enumerate() {
+                packer.insert(i, width, height).unwrap();
+            }
+            area as f64 / f64::from(packer.size().width * packer.size().height)
+        };
+        let width = (area as f64 * 1.1).sqrt() as u32;
+        let grown = occupancy(Skyline::new(SkylineHeuristic::BottomLeft));
+        let fixed = occupancy(Skyline::with_initial_width(SkylineHeuristic::BottomLeft, width,
+            BinLimits::default()));
+        assert!(fixed > 0.85 && fixed > grown, "{} vs {}", fixed, grown);
+    }
+}
@@ -1,7 +1,13 @@
-use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
 use std::iter::{Iterator, ExactSizeIterator, FusedIterator};
 
-#[derive(Clone, Debug, PartialEq, Eq, Default)]
+use serde::{Deserialize, Serialize};
+
+use crate::packer::{DoesNotFit, BinConstraints};
+
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Region {
     pub top: u32,
     pub left: u32,
@@ -9,50 +15,85 @@ pub struct Region {
     pub height: u32,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Size {
     pub width: u32,
     pub height: u32,
 }
 
-#[derive(Clone, Debug, Default)]
+/// Index of a node in a [`SpatialTree`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SpatialNode<T> {
-    pub region: Region,
+    region: Region,
     pub value: Option<T>,
-    pub value_size: (u32, u32),
-    pub right: Option<Box<SpatialNode<T>>>,
-    pub bottom: Option<Box<SpatialNode<T>>>,
-    pub parent: Option<*mut SpatialNode<T>>,
+    value_size: (u32, u32),
+    right: Option<NodeId>,
+    bottom: Option<NodeId>,
+    parent: Option<NodeId>,
 }
 
-#[derive(Debug)]
+/// Guillotine packer storing its nodes in an arena. Each placed item splits its node into a
+/// free region to the right of the item and one below it; growing the tree adds a new root with
+/// the old tree and the new space as children. Nodes without an item whose children split their
+/// region in some other way are containers, created by growing and by removing items.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "SpatialTreeData<T>")]
 pub struct SpatialTree<T> {
     num_items: usize,
     region: Region,
-    root: Option<Box<SpatialNode<T>>>,
-    free_regions: HashSet<*mut SpatialNode<T>>,
+    max_size: Size,
+    allow_rotation: bool,
+    constraints: BinConstraints,
+    nodes: Vec<SpatialNode<T>>,
+    /// Slots of nodes discarded when free space was merged, reused by later splits.
+    vacant: Vec<NodeId>,
+    root: Option<NodeId>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum TraverseDirection {
-    Right,
-    Down,
-    None,
+/// A deserialized [`SpatialTree`] as written, before its structure has been checked.
+#[derive(Deserialize)]
+struct SpatialTreeData<T> {
+    num_items: usize,
+    region: Region,
+    max_size: Size,
+    allow_rotation: bool,
+    constraints: BinConstraints,
+    nodes: Vec<SpatialNode<T>>,
+    vacant: Vec<NodeId>,
+    root: Option<NodeId>,
 }
 
+/// Why a deserialized [`SpatialTree`] was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidTree(&'static str);
+
+/// Pre-order (node, right subtree, bottom subtree) walk over the nodes of a tree.
 #[derive(Debug)]
-pub struct SpatialTreeNodeIterMut<'a, T> {
+pub struct SpatialTreeNodeIter<'a, T> {
     tree: &'a SpatialTree<T>,
-    node: Option<*mut SpatialNode<T>>,
-    stack: Vec<TraverseDirection>,
+    stack: Vec<NodeId>,
+    remaining: usize,
 }
+/// The same walk as [`SpatialTreeNodeIter`] handing out every node mutably. Kept private, since
+/// changing anything but a node's value could break the tree.
+#[derive(Debug)]
+struct SpatialTreeNodeIterMut<'a, T> {
+    nodes: Vec<Option<&'a mut SpatialNode<T>>>,
+    order: std::vec::IntoIter<NodeId>,
+}
+/// Placed items with their regions, in the same order as the nodes holding them.
 #[derive(Debug)]
 pub struct SpatialTreeIter<'a, T> {
-    node_iter: SpatialTreeNodeIterMut<'a, T>,
+    node_iter: SpatialTreeNodeIter<'a, T>,
+    remaining: usize,
 }
 #[derive(Debug)]
 pub struct SpatialTreeIterMut<'a, T> {
     node_iter: SpatialTreeNodeIterMut<'a, T>,
+    remaining: usize,
 }
 
 impl Region {
@@ -80,6 +121,32 @@ impl Region {
     pub fn max_size(&self) -> u32 {
         self.width.max(self.height)
     }
+
+    pub fn contains_point(&self, x: u32, y: u32) -> bool {
+        x >= self.left && x < self.right() && y >= self.top && y < self.bottom()
+    }
+
+    /// Whether `other` lies entirely within this region.
+    pub fn contains(&self, other: &Region) -> bool {
+        other.left >= self.left && other.top >= self.top
+            && other.right() <= self.right() && other.bottom() <= self.bottom()
+    }
+
+    /// Whether the regions share at least one pixel; merely touching edges don't count.
+    pub fn intersects(&self, other: &Region) -> bool {
+        self.left < other.right() && other.left < self.right()
+            && self.top < other.bottom() && other.top < self.bottom()
+    }
+
+    pub fn intersection(&self, other: &Region) -> Option<Region> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let left = self.left.max(other.left);
+        let top = self.top.max(other.top);
+        Some(Region::new(top, left, self.right().min(other.right()) - left,
+            self.bottom().min(other.bottom()) - top))
+    }
 }
 
 impl<T> SpatialNode<T> {
@@ -93,18 +160,55 @@ impl<T> SpatialNode<T> {
             value_size: (0, 0),
         }
     }
+
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+    pub fn value_size(&self) -> (u32, u32) {
+        self.value_size
+    }
+    pub fn right(&self) -> Option<NodeId> {
+        self.right
+    }
+    pub fn bottom(&self) -> Option<NodeId> {
+        self.bottom
+    }
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn value_region(&self) -> Region {
+        Region::new(self.region.top, self.region.left, self.value_size.0, self.value_size.1)
+    }
+
+    /// An empty leaf, available for new items.
+    pub fn is_free(&self) -> bool {
+        self.value.is_none() && self.right.is_none() && self.bottom.is_none()
+    }
+
 }
 
 impl<'a, T> SpatialTree<T> {
-    pub fn iter_nodes(&'a mut self) -> SpatialTreeNodeIterMut<'a, T> {
+    pub fn iter_nodes(&'a self) -> SpatialTreeNodeIter<'a, T> {
+        SpatialTreeNodeIter::new(self)
+    }
+    fn iter_nodes_mut(&'a mut self) -> SpatialTreeNodeIterMut<'a, T> {
         SpatialTreeNodeIterMut::new(self)
     }
-    /*
     pub fn iter(&'a self) -> SpatialTreeIter<'a, T> {
-        SpatialTreeIter { node_iter: SpatialTreeNodeIter::new(unsafe { std::mem::transmute::<_, &'a mut Self>(self) }) }
-    }*/
-    pub fn iter_mut(&'a mut self) -> SpatialTreeIter<'a, T> {
-        SpatialTreeIter { node_iter: SpatialTreeNodeIterMut::new(self) }
+        SpatialTreeIter { remaining: self.num_items, node_iter: SpatialTreeNodeIter::new(self) }
+    }
+    pub fn iter_mut(&'a mut self) -> SpatialTreeIterMut<'a, T> {
+        SpatialTreeIterMut { remaining: self.num_items, node_iter: self.iter_nodes_mut() }
+    }
+    pub fn placements(&'a self) -> SpatialTreeIter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Default for SpatialTree<T> {
+    fn default() -> SpatialTree<T> {
+        SpatialTree::new()
     }
 }
 
@@ -113,8 +217,25 @@ impl<T> SpatialTree<T> {
         SpatialTree {
             num_items: 0,
             region: Region::default(),
+            max_size: Size { width: u32::MAX, height: u32::MAX },
+            allow_rotation: false,
+            constraints: BinConstraints::default(),
+            nodes: Vec::new(),
+            vacant: Vec::new(),
+            root: None,
+        }
+    }
+
+    pub fn with_max_size(width: u32, height: u32) -> SpatialTree<T> {
+        SpatialTree {
+            num_items: 0,
+            region: Region::default(),
+            max_size: Size { width, height },
+            allow_rotation: false,
+            constraints: BinConstraints::default(),
+            nodes: Vec::new(),
+            vacant: Vec::new(),
             root: None,
-            free_regions: HashSet::new(),
         }
     }
 
@@ -122,8 +243,12 @@ impl<T> SpatialTree<T> {
         SpatialTree {
             num_items: 0,
             region: Region::new(0, 0, width, height),
+            max_size: Size { width: u32::MAX, height: u32::MAX },
+            allow_rotation: false,
+            constraints: BinConstraints::default(),
+            nodes: Vec::new(),
+            vacant: Vec::new(),
             root: None,
-            free_regions: HashSet::new(),
         }
     }
 
@@ -133,53 +258,257 @@ impl<T> SpatialTree<T> {
     pub fn region(&self) -> &Region {
         &self.region
     }
+    pub fn max_size(&self) -> &Size {
+        &self.max_size
+    }
+    pub fn root(&self) -> Option<&SpatialNode<T>> {
+        self.root.map(|id| self.node(id))
+    }
+    pub fn node(&self, id: NodeId) -> &SpatialNode<T> {
+        &self.nodes[id.0]
+    }
 
-    pub fn insert(&mut self, item: T, width: u32, height: u32) {
-        let region = Region {
-            top: 0,
-            left: 0,
-            width,
-            height
-        };
-        if let Some(root) = self.root.as_mut() {
-            let mut target_node = None;
-            for node in self.iter_nodes() {
-                if node.value.is_none() && node.region.width >= width && node.region.height >= height && node.right.is_none() {
-                    target_node = Some(unsafe { &mut *std::mem::transmute::<_, *mut SpatialNode<T>>(node) });
-                    break;
+    /// Finds the item covering pixel (`x`, `y`), following only the children whose region
+    /// contains the point.
+    pub fn item_at(&self, x: u32, y: u32) -> Option<(&T, Region)> {
+        let mut current = self.root;
+        while let Some(id) = current {
+            let node = self.node(id);
+            if !node.region.contains_point(x, y) {
+                return None;
+            }
+            let value_region = node.value_region();
+            if value_region.contains_point(x, y) {
+                if let Some(value) = node.value.as_ref() {
+                    return Some((value, value_region));
                 }
             }
-            if let Some(node) = target_node {
-                self.split_node(node, &region);
-                node.value = Some(item);
-                node.value_size = (width, height);
-            } else {
-                let node = unsafe{&mut *std::mem::transmute::<_, *mut SpatialNode<T>>(self.resize(&region))};
-                self.split_node(node, &region);
-                node.value = Some(item);
-                node.value_size = (width, height);
+            current = [node.right, node.bottom].iter().flatten()
+                .find(|&&child| self.node(child).region.contains_point(x, y))
+                .cloned();
+        }
+        None
+    }
+
+    /// Collects the items overlapping `region`, skipping subtrees that lie outside it.
+    pub fn items_intersecting(&self, region: &Region) -> Vec<(&T, Region)> {
+        let mut items = Vec::new();
+        let mut stack = self.root.into_iter().collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            let node = self.node(id);
+            if !node.region.intersects(region) {
+                continue;
             }
-        } else {
-            if self.region.width == 0 {
-                self.region = region.clone();
+            if let Some(value) = node.value.as_ref() {
+                let value_region = node.value_region();
+                if value_region.intersects(region) {
+                    items.push((value, value_region));
+                }
             }
-            let mut new_root = Box::new(SpatialNode {
+            stack.extend(node.bottom);
+            stack.extend(node.right);
+        }
+        items
+    }
+
+    pub fn set_allow_rotation(&mut self, allow_rotation: bool) {
+        self.allow_rotation = allow_rotation;
+    }
+    pub fn allow_rotation(&self) -> bool {
+        self.allow_rotation
+    }
+    /// Constraints the final atlas will be rounded up to. The tree grows in whichever direction
+    /// wastes the least area after rounding; the rounding itself happens on the final canvas.
+    pub fn set_size_constraints(&mut self, constraints: BinConstraints) {
+        self.constraints = constraints;
+    }
+    /// Changes how far the tree may grow. Space it already covers is kept even if it's larger.
+    pub fn set_max_size(&mut self, width: u32, height: u32) {
+        self.max_size = Size { width, height };
+    }
+
+    /// Copies the layout with every placed item converted by `f`.
+    pub fn map<U, F>(&self, mut f: F) -> SpatialTree<U>
+        where F: FnMut(&T) -> U
+    {
+        SpatialTree {
+            num_items: self.num_items,
+            region: self.region.clone(),
+            max_size: self.max_size.clone(),
+            allow_rotation: self.allow_rotation,
+            constraints: self.constraints,
+            nodes: self.nodes.iter()
+                .map(|node| SpatialNode {
+                    region: node.region.clone(),
+                    value: node.value.as_ref().map(&mut f),
+                    value_size: node.value_size,
+                    right: node.right,
+                    bottom: node.bottom,
+                    parent: node.parent,
+                })
+                .collect(),
+            vacant: self.vacant.clone(),
+            root: self.root,
+        }
+    }
+
+    pub fn insert(&mut self, item: T, width: u32, height: u32) -> Result<Region, DoesNotFit<T>> {
+        let upright = Region::new(0, 0, width, height);
+        let rotated = Region::new(0, 0, height, width);
+        let allow_rotation = self.allow_rotation && width != height;
+        let upright_fits = width <= self.max_size.width && height <= self.max_size.height;
+        let rotated_fits = allow_rotation && height <= self.max_size.width && width <= self.max_size.height;
+        if !upright_fits && !rotated_fits {
+            return Err(DoesNotFit { item, width, height });
+        }
+        let placed;
+        if self.root.is_some() {
+            let mut target_node = None;
+            let mut nodes = self.iter_nodes();
+            while let Some(id) = nodes.next_id() {
+                let node = self.node(id);
+                if node.is_free() {
+                    if let Some(rotate) = Self::fit_orientation(&node.region, width, height, allow_rotation) {
+                        target_node = Some((id, rotate));
+                        break;
+                    }
+                }
+            }
+            let (id, region) = match target_node {
+                Some((id, rotate)) => (id, if rotate { rotated } else { upright }),
+                None => {
+                    let region = match self.resize_orientation(&upright, &rotated, allow_rotation) {
+                        Some(region) => region,
+                        None => return Err(DoesNotFit { item, width, height }),
+                    };
+                    let id = match self.resize(&region) {
+                        Some(id) => id,
+                        None => return Err(DoesNotFit { item, width, height }),
+                    };
+                    (id, region)
+                },
+            };
+            self.split_node(id, &region);
+            let node = &mut self.nodes[id.0];
+            node.value = Some(item);
+            node.value_size = (region.width, region.height);
+            placed = node.value_region();
+        } else {
+            // The first item goes in the top left corner of the initial region, which grows to
+            // hold it if it's too small.
+            let region = match Self::fit_orientation(&self.region, width, height, allow_rotation) {
+                Some(true) => rotated,
+                Some(false) => upright,
+                None if upright_fits => upright,
+                None => rotated,
+            };
+            self.region = Region::new(0, 0, self.region.width.max(region.width),
+                self.region.height.max(region.height));
+            let root = self.add_node(SpatialNode {
                 region: self.region.clone(),
                 value: Some(item),
                 right: None,
                 bottom: None,
                 parent: None,
-                value_size: (width, height),
+                value_size: (region.width, region.height),
             });
-            self.split_node(new_root.as_mut(), &region.clone());
-            self.root = Some(new_root);
+            self.split_node(root, &region);
+            placed = self.node(root).value_region();
+            self.root = Some(root);
         }
         self.num_items += 1;
+        Ok(placed)
     }
 
-    fn split_node<'a>(&mut self, node: &'a mut SpatialNode<T>, new_region: &Region)
-        -> (&'a mut SpatialNode<T>, &'a mut SpatialNode<T>)
+    /// Removes `id` from the tree and returns it. The space it occupied becomes a free leaf for
+    /// later inserts, merged with any free neighbouring space. The tree itself never shrinks.
+    pub fn remove(&mut self, id: &T) -> Option<T>
+        where T: PartialEq
     {
+        let removed = self.remove_from(self.root?, id)?;
+        self.num_items -= 1;
+        Some(removed)
+    }
+
+    fn remove_from(&mut self, node: NodeId, id: &T) -> Option<T>
+        where T: PartialEq
+    {
+        let removed = if self.node(node).value.as_ref() == Some(id) {
+            let removed = self.nodes[node.0].value.take();
+            self.vacate(node);
+            removed
+        } else {
+            let (right, bottom) = (self.node(node).right, self.node(node).bottom);
+            right.and_then(|right| self.remove_from(right, id))
+                .or_else(|| bottom.and_then(|bottom| self.remove_from(bottom, id)))
+        };
+        if removed.is_some() {
+            self.coalesce(node);
+        }
+        removed
+    }
+
+    /// Makes the space an item held in node `id` available again. The node had been split into
+    /// the item, the rest of the item's row to its right and everything below; it becomes a
+    /// container of that row, now a free leaf where the item was plus the rest of the row, and
+    /// the space below. The free leaf comes first so inserts try the old spot first.
+    fn vacate(&mut self, id: NodeId) {
+        let node = self.node(id);
+        let (row_rest, below) = (node.right, node.bottom);
+        if row_rest.is_none() && below.is_none() {
+            self.nodes[id.0].value_size = (0, 0);
+            return;
+        }
+        let slot = node.value_region();
+        let row_region = Region::new(node.region.top, node.region.left, node.region.width, slot.height);
+        let slot = self.add_node(SpatialNode::empty(slot));
+        let row = self.add_node(SpatialNode {
+            region: row_region,
+            value: None,
+            value_size: (0, 0),
+            right: Some(slot),
+            bottom: row_rest,
+            parent: Some(id),
+        });
+        for child in Some(slot).iter().chain(row_rest.iter()) {
+            self.nodes[child.0].parent = Some(row);
+        }
+        let node = &mut self.nodes[id.0];
+        node.right = Some(row);
+        node.value_size = (0, 0);
+        self.coalesce(row);
+    }
+
+    /// Turns the node back into a single free leaf if it holds nothing and both of its children
+    /// are free.
+    fn coalesce(&mut self, id: NodeId) {
+        let node = self.node(id);
+        let children = [node.right, node.bottom];
+        let child_free = |child: &Option<NodeId>| child.is_none_or(|child| self.node(child).is_free());
+        if node.value.is_none() && children.iter().all(child_free) {
+            self.vacant.extend(children.iter().flatten());
+            let node = &mut self.nodes[id.0];
+            node.right = None;
+            node.bottom = None;
+            node.value_size = (0, 0);
+        }
+    }
+
+    fn add_node(&mut self, node: SpatialNode<T>) -> NodeId {
+        match self.vacant.pop() {
+            Some(id) => {
+                self.nodes[id.0] = node;
+                id
+            },
+            None => {
+                self.nodes.push(node);
+                NodeId(self.nodes.len() - 1)
+            },
+        }
+    }
+
+    fn split_node(&mut self, id: NodeId, new_region: &Region) -> (NodeId, NodeId) {
+        let node = self.node(id);
         assert!(node.right.is_none() && node.bottom.is_none());
         let right_region = Region {
             left: node.region.left + new_region.width,
@@ -193,40 +522,110 @@ impl<T> SpatialTree<T> {
             width: node.region.width,
             height: node.region.height - new_region.height,
         };
-        let mut right_node = Box::new(SpatialNode::empty(right_region));
-        let mut bottom_node = Box::new(SpatialNode::empty(bottom_region));
-        right_node.as_mut().parent = Some(node);
-        bottom_node.as_mut().parent = Some(node);
-        self.free_regions.insert(right_node.as_mut() as *mut SpatialNode<T>);
-        self.free_regions.insert(bottom_node.as_mut() as *mut SpatialNode<T>);
-        node.right = Some(right_node);
-        node.bottom = Some(bottom_node);
-        (node.right.as_mut().unwrap().as_mut(), node.bottom.as_mut().unwrap().as_mut())
+        let right = self.add_node(SpatialNode { parent: Some(id), ..SpatialNode::empty(right_region) });
+        let bottom = self.add_node(SpatialNode { parent: Some(id), ..SpatialNode::empty(bottom_region) });
+        let node = &mut self.nodes[id.0];
+        node.right = Some(right);
+        node.bottom = Some(bottom);
+        (right, bottom)
+    }
 
+    /// Decides whether an item of `width` x `height` sits upright or rotated in an empty
+    /// `region`, preferring the orientation that leaves the shorter leftover side smaller.
+    fn fit_orientation(region: &Region, width: u32, height: u32, allow_rotation: bool) -> Option<bool> {
+        let upright = region.width >= width && region.height >= height;
+        let rotated = allow_rotation && region.width >= height && region.height >= width;
+        match (upright, rotated) {
+            (true, true) => {
+                let upright_leftover = (region.width - width).min(region.height - height);
+                let rotated_leftover = (region.width - height).min(region.height - width);
+                Some(rotated_leftover < upright_leftover)
+            },
+            (true, false) => Some(false),
+            (false, true) => Some(true),
+            (false, false) => None,
+        }
     }
 
-    fn resize(&mut self, new_region: &Region) -> &mut SpatialNode<T> {
+    /// Returns true to grow to the right and false to grow down when making room for
+    /// `new_region`, or `None` if neither stays within the maximum size.
+    fn growth_direction(&self, new_region: &Region) -> Option<bool> {
         let new_width = self.region.width + new_region.width;
         let new_height = self.region.height + new_region.height;
+        let fits_right = new_width <= self.max_size.width
+            && new_region.height.max(self.region.height) <= self.max_size.height;
+        let fits_down = new_height <= self.max_size.height
+            && new_region.width.max(self.region.width) <= self.max_size.width;
 
-        if new_width < new_height {
-            if new_region.height > self.region.height {
-                //TODO
-                //panic!("Height overflow")
-                self.resize_down(self.region.width, new_region.height);
+        match (fits_right, fits_down) {
+            (true, true) if self.constraints.is_constrained() => {
+                let right_area = self.constraints.area(&self.grown_size(new_region, true));
+                let down_area = self.constraints.area(&self.grown_size(new_region, false));
+                if right_area == down_area {
+                    Some(new_width < new_height)
+                } else {
+                    Some(right_area < down_area)
+                }
+            },
+            (true, true) => Some(new_width < new_height),
+            (true, false) => Some(true),
+            (false, true) => Some(false),
+            (false, false) => None,
+        }
+    }
+
+    /// Size of the tree after growing right or down to make room for `new_region`.
+    fn grown_size(&self, new_region: &Region, right: bool) -> Size {
+        if right {
+            Size {
+                width: self.region.width + new_region.width,
+                height: self.region.height.max(new_region.height),
             }
-            self.resize_right(new_width, self.region.height)
         } else {
-            if new_region.width > self.region.width {
-                //TODO
-                //panic!("Width overflow")
-                self.resize_right(new_region.width, self.region.height);
+            Size {
+                width: self.region.width.max(new_region.width),
+                height: self.region.height + new_region.height,
             }
-            self.resize_down(self.region.width, new_height)
         }
     }
 
-    fn resize_right(&mut self, new_width: u32, height: u32) -> &mut SpatialNode<T> {
+    fn grown_area(&self, new_region: &Region) -> Option<u64> {
+        let right = self.growth_direction(new_region)?;
+        Some(self.constraints.area(&self.grown_size(new_region, right)))
+    }
+
+    /// Picks the orientation that grows the tree the least.
+    fn resize_orientation(&self, upright: &Region, rotated: &Region, allow_rotation: bool) -> Option<Region> {
+        let upright_area = self.grown_area(upright);
+        let rotated_area = if allow_rotation { self.grown_area(rotated) } else { None };
+        match (upright_area, rotated_area) {
+            (Some(a), Some(b)) if b < a => Some(rotated.clone()),
+            (Some(_), _) => Some(upright.clone()),
+            (None, Some(_)) => Some(rotated.clone()),
+            (None, None) => None,
+        }
+    }
+
+    fn resize(&mut self, new_region: &Region) -> Option<NodeId> {
+        let right = self.growth_direction(new_region)?;
+        let grown = self.grown_size(new_region, right);
+
+        if right {
+            if grown.height > self.region.height {
+                self.resize_down(self.region.width, grown.height);
+            }
+            Some(self.resize_right(grown.width, self.region.height))
+        } else {
+            if grown.width > self.region.width {
+                self.resize_right(grown.width, self.region.height);
+            }
+            Some(self.resize_down(self.region.width, grown.height))
+        }
+    }
+
+    /// Makes the current tree the bottom child of a new root `new_width` wide, with the new
+    /// space to its right, and returns the new space.
+    fn resize_right(&mut self, new_width: u32, height: u32) -> NodeId {
         let full_region = Region {
             left: 0,
             top: 0,
@@ -239,25 +638,13 @@ impl<T> SpatialTree<T> {
             width: new_width - self.region.width,
             height,
         };
-        let right_node = SpatialNode::empty(right_region);
-        let mut new_root= Box::new(SpatialNode {
-            region: full_region,
-            value: None,
-            bottom: self.root.take(),
-            right: Some(Box::new(right_node)),
-            parent: None,
-            value_size: (new_width, height),
-        });
-        new_root.bottom.as_mut().unwrap().parent = Some(new_root.as_mut());
-        new_root.right.as_mut().unwrap().parent = Some(new_root.as_mut());
-        self.region = new_root.region.clone();
-        self.root = Some(new_root);
-        let new_node = self.root.as_mut().unwrap().right.as_mut().unwrap();
-        self.free_regions.insert(new_node.as_mut() as *mut SpatialNode<T>);
-        new_node
+        let right = self.add_node(SpatialNode::empty(right_region));
+        self.grow_root(full_region, Some(right), self.root)
     }
 
-    fn resize_down(&mut self, width: u32, new_height: u32) -> &mut SpatialNode<T> {
+    /// Makes the current tree the right child of a new root `new_height` tall, with the new
+    /// space below it, and returns the new space.
+    fn resize_down(&mut self, width: u32, new_height: u32) -> NodeId {
         let full_region = Region {
             left: 0,
             top : 0,
@@ -270,122 +657,209 @@ impl<T> SpatialTree<T> {
             width,
             height: new_height - self.region.height,
         };
-        let bottom_node = SpatialNode::empty(bottom_region);
-        let mut new_root = Box::new(SpatialNode {
-            region: full_region,
+        let bottom = self.add_node(SpatialNode::empty(bottom_region));
+        self.grow_root(full_region, self.root, Some(bottom))
+    }
+
+    fn grow_root(&mut self, region: Region, right: Option<NodeId>, bottom: Option<NodeId>) -> NodeId {
+        let new_node = if right == self.root { bottom } else { right }.unwrap();
+        let root = self.add_node(SpatialNode {
+            value_size: (region.width, region.height),
+            region: region.clone(),
             value: None,
-            bottom: Some(Box::new(bottom_node)),
-            right: self.root.take(),
+            right,
+            bottom,
             parent: None,
-            value_size: (width, new_height),
         });
-        new_root.bottom.as_mut().unwrap().parent = Some(new_root.as_mut());
-        new_root.right.as_mut().unwrap().parent = Some(new_root.as_mut());
-        self.region = new_root.region.clone();
-        self.root = Some(new_root);
-        let new_node = self.root.as_mut().unwrap().bottom.as_mut().unwrap();
-        self.free_regions.insert(new_node.as_mut() as *mut SpatialNode<T>);
+        for child in right.iter().chain(bottom.iter()) {
+            self.nodes[child.0].parent = Some(root);
+        }
+        self.region = region;
+        self.root = Some(root);
         new_node
     }
-
 }
 
-impl<'a, T: std::fmt::Display> SpatialTree<T> {
+impl<T: std::fmt::Display> SpatialTree<T> {
     pub fn display_recursive(&self, node: Option<&SpatialNode<T>>, depth: usize) {
         if let Some(n) = node {
-            let padding = std::iter::repeat(" ").take(depth * 2).collect::<String>();
+            let padding = "  ".repeat(depth);
             if let Some(value) = &n.value {
                 println!("{}{} ({}, {}) {}x{}", padding, value, n.region.top, n.region.left, n.region.width, n.region.height);
             } else {
                 println!("{}EMPTY ({}, {}) {}x{}", padding, n.region.top, n.region.left, n.region.width, n.region.height);
             }
-            self.display_recursive(n.right.as_ref().map(|x| x.as_ref()), depth+1);
-            self.display_recursive(n.bottom.as_ref().map(|x| x.as_ref()), depth+1);
+            self.display_recursive(n.right.map(|id| self.node(id)), depth+1);
+            self.display_recursive(n.bottom.map(|id| self.node(id)), depth+1);
         }
     }
 }
 
-impl<'a, T: std::fmt::Display> std::fmt::Display for SpatialTree<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        self.display_recursive(self.root.as_ref().map(|x| x.as_ref()), 0);
+impl<T: std::fmt::Display> std::fmt::Display for SpatialTree<T> {
+    fn fmt(&self, _f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.display_recursive(self.root(), 0);
         Ok(())
     }
 }
 
+impl<T> TryFrom<SpatialTreeData<T>> for SpatialTree<T> {
+    type Error = InvalidTree;
 
-impl<'a, T> SpatialTreeNodeIterMut<'a, T> {
-    fn new(tree: &'a mut SpatialTree<T>) -> SpatialTreeNodeIterMut<T> {
-        SpatialTreeNodeIterMut {
-            node: tree.root.as_mut().map(|x| x.as_mut() as *mut SpatialNode<T>),
-            tree,
-            stack: vec![TraverseDirection::None],
+    /// Checks everything the tree relies on without checking: node ids in range, every slot
+    /// either vacant or reached exactly once from the root, parent links matching, regions
+    /// nested inside their parents and `num_items` matching the stored items.
+    fn try_from(data: SpatialTreeData<T>) -> Result<SpatialTree<T>, InvalidTree> {
+        let nodes = &data.nodes;
+        let in_range = |id: &NodeId| id.0 < nodes.len();
+        // Compared in u64 so that regions reaching past u32::MAX can't overflow here.
+        let within = |inner: &Region, outer: &Region| {
+            let (left, top) = (u64::from(inner.left), u64::from(inner.top));
+            left >= u64::from(outer.left) && top >= u64::from(outer.top)
+                && left + u64::from(inner.width) <= u64::from(outer.left) + u64::from(outer.width)
+                && top + u64::from(inner.height) <= u64::from(outer.top) + u64::from(outer.height)
+        };
+        let all = Region::new(0, 0, u32::MAX, u32::MAX);
+        if !within(&data.region, &all) {
+            return Err(InvalidTree("the tree's region is too large"));
+        }
+
+        let mut seen = vec![false; nodes.len()];
+        for id in &data.vacant {
+            if !in_range(id) || seen[id.0] {
+                return Err(InvalidTree("a vacant slot is out of range or listed twice"));
+            }
+            seen[id.0] = true;
         }
+        let mut items = 0;
+        if let Some(root) = data.root {
+            if !in_range(&root) {
+                return Err(InvalidTree("the root is out of range"));
+            }
+            if nodes[root.0].parent.is_some() {
+                return Err(InvalidTree("the root has a parent"));
+            }
+            if nodes[root.0].region != data.region {
+                return Err(InvalidTree("the root doesn't cover the tree's region"));
+            }
+            let mut stack = vec![root];
+            while let Some(id) = stack.pop() {
+                if seen[id.0] {
+                    return Err(InvalidTree("a node is vacant or reachable more than once"));
+                }
+                seen[id.0] = true;
+                let node = &nodes[id.0];
+                if !within(&node.value_region(), &node.region) {
+                    return Err(InvalidTree("an item is larger than its node"));
+                }
+                if node.value.is_some() {
+                    items += 1;
+                }
+                for child in node.right.iter().chain(node.bottom.iter()) {
+                    if !in_range(child) {
+                        return Err(InvalidTree("a child is out of range"));
+                    }
+                    if nodes[child.0].parent != Some(id) {
+                        return Err(InvalidTree("a child doesn't link back to its parent"));
+                    }
+                    if !within(&nodes[child.0].region, &node.region) {
+                        return Err(InvalidTree("a child reaches outside its parent"));
+                    }
+                    stack.push(*child);
+                }
+            }
+        }
+        if seen.contains(&false) {
+            return Err(InvalidTree("a node is neither in the tree nor vacant"));
+        }
+        if items != data.num_items {
+            return Err(InvalidTree("the item count doesn't match the stored items"));
+        }
+
+        Ok(SpatialTree {
+            num_items: data.num_items,
+            region: data.region,
+            max_size: data.max_size,
+            allow_rotation: data.allow_rotation,
+            constraints: data.constraints,
+            nodes: data.nodes,
+            vacant: data.vacant,
+            root: data.root,
+        })
     }
+}
 
-    fn traverse_right(&mut self) -> bool {
-        let node = unsafe { &mut *self.node.unwrap() };
-        if let Some(right) = node.right.as_mut().map(|x| x.as_mut()) {
-            self.node = Some(right as *mut SpatialNode<T>);
-            self.stack.pop();
-            self.stack.push(TraverseDirection::Right);
-            self.stack.push(TraverseDirection::None);
-            true
-        } else {
-            false
+impl fmt::Display for InvalidTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid layout: {}", self.0)
+    }
+}
+
+impl Error for InvalidTree {}
+
+
+impl<'a, T> SpatialTreeNodeIter<'a, T> {
+    fn new(tree: &'a SpatialTree<T>) -> SpatialTreeNodeIter<'a, T> {
+        SpatialTreeNodeIter {
+            tree,
+            stack: tree.root.into_iter().collect(),
+            remaining: if tree.root.is_some() { tree.nodes.len() - tree.vacant.len() } else { 0 },
         }
     }
 
-    fn traverse_bottom(&mut self) -> bool {
-        let node = unsafe { &mut *self.node.unwrap() };
-        if let Some(down) = node.bottom.as_mut().map(|x| x.as_mut()) {
-            self.node = Some(down as *mut SpatialNode<T>);
-            self.stack.pop();
-            self.stack.push(TraverseDirection::Down);
-            self.stack.push(TraverseDirection::None);
-            true
-        } else {
-            false
+    fn next_id(&mut self) -> Option<NodeId> {
+        let id = self.stack.pop()?;
+        self.remaining -= 1;
+        let node = self.tree.node(id);
+        self.stack.extend(node.bottom);
+        self.stack.extend(node.right);
+        Some(id)
+    }
+}
+
+impl<'a, T> Iterator for SpatialTreeNodeIter<'a, T> {
+    type Item = &'a SpatialNode<T>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = self.tree;
+        self.next_id().map(|id| tree.node(id))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for SpatialTreeNodeIter<'a, T> {}
+impl<'a, T> FusedIterator for SpatialTreeNodeIter<'a, T> {}
+
+impl<'a, T> SpatialTreeNodeIterMut<'a, T> {
+    fn new(tree: &'a mut SpatialTree<T>) -> SpatialTreeNodeIterMut<'a, T> {
+        let order = SpatialTreeNodeIter::new(tree).ids();
+        SpatialTreeNodeIterMut {
+            nodes: tree.nodes.iter_mut().map(Some).collect(),
+            order: order.into_iter(),
         }
     }
+}
 
-    fn traverse_to_parent(&mut self) {
-        self.stack.pop();
-        self.node = self.node.as_mut().map(|x| unsafe { &**x }).unwrap().parent.map(|x| x as *mut SpatialNode<T>);
+impl<'a, T> SpatialTreeNodeIter<'a, T> {
+    fn ids(mut self) -> Vec<NodeId> {
+        let mut ids = Vec::with_capacity(self.tree.nodes.len());
+        while let Some(id) = self.next_id() {
+            ids.push(id);
+        }
+        ids
     }
 }
 
 impl<'a, T> Iterator for SpatialTreeNodeIterMut<'a, T> {
     type Item = &'a mut SpatialNode<T>;
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(node) = self.node.map(|x| unsafe { &mut *x }) {
-            let orig_dir = self.stack.last().unwrap().clone();
-            match self.stack.last().unwrap() {
-                TraverseDirection::None => {
-                    if !self.traverse_right() && !self.traverse_bottom() {
-                        self.traverse_to_parent()
-                    }
-                },
-                TraverseDirection::Right => {
-                    if !self.traverse_bottom() {
-                        self.traverse_to_parent()
-                    }
-                },
-                TraverseDirection::Down => {
-                    self.traverse_to_parent()
-                },
-            };
-            if orig_dir == TraverseDirection::None {
-                Some(node)
-            } else {
-                self.next()
-            }
-        } else {
-            None
-        }
+        let id = self.order.next()?;
+        self.nodes[id.0].take()
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.tree.num_items, Some(self.tree.num_items))
+        self.order.size_hint()
     }
 }
 
@@ -393,45 +867,248 @@ impl<'a, T> ExactSizeIterator for SpatialTreeNodeIterMut<'a, T> {}
 impl<'a, T> FusedIterator for SpatialTreeNodeIterMut<'a, T> {}
 
 impl<'a, T> Iterator for SpatialTreeIter<'a, T> {
-    type Item = &'a T;
+    type Item = (&'a T, Region);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if let Some(node) = self.node_iter.next() {
-                if let Some(value) = node.value.as_ref() {
-                    return Some(value)
-                }
-            } else {
-                return None
-            }
-        }
+        let placement = self.node_iter
+            .find_map(|node| node.value.as_ref().map(|value| (value, node.value_region())))?;
+        self.remaining -= 1;
+        Some(placement)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.node_iter.size_hint()
+        (self.remaining, Some(self.remaining))
     }
 }
 impl<'a, T> ExactSizeIterator for SpatialTreeIter<'a, T> {}
 impl<'a, T> FusedIterator for SpatialTreeIter<'a, T> {}
 
 impl<'a, T> Iterator for SpatialTreeIterMut<'a, T> {
-    type Item = &'a mut T;
+    type Item = (&'a mut T, Region);
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if let Some(node) = self.node_iter.next() {
-                if let Some(value) = node.value.as_mut() {
-                    return Some(value)
-                }
-            } else {
-                return None
-            }
-        }
+        let placement = self.node_iter.find_map(|node| {
+            let region = node.value_region();
+            node.value.as_mut().map(|value| (value, region))
+        })?;
+        self.remaining -= 1;
+        Some(placement)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.node_iter.size_hint()
+        (self.remaining, Some(self.remaining))
     }
 }
 impl<'a, T> ExactSizeIterator for SpatialTreeIterMut<'a, T> {}
 impl<'a, T> FusedIterator for SpatialTreeIterMut<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Item sizes from a fixed linear congruential sequence.
+    fn sizes(count: usize, seed: u64) -> Vec<(u32, u32)> {
+        let mut state = seed;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            1 + ((state >> 33) % 30) as u32
+        };
+        (0..count).map(|_| (next(), next())).collect()
+    }
+
+    fn assert_disjoint(tree: &SpatialTree<usize>) {
+        let placements = tree.iter().collect::<Vec<_>>();
+        for (i, (_, a)) in placements.iter().enumerate() {
+            assert!(tree.region().contains(a));
+            for (_, b) in &placements[i + 1..] {
+                assert!(!a.intersects(b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    /// Inserts `sizes` in order, returning each placement as (left, top, width, height).
+    fn placements(tree: &mut SpatialTree<usize>, sizes: &[(u32, u32)])
+        -> Vec<Option<(u32, u32, u32, u32)>>
+    {
+        sizes.iter().enumerate()
+            .map(|(i, &(width, height))| tree.insert(i, width, height).ok()
+                .map(|region| (region.left, region.top, region.width, region.height)))
+            .collect()
+    }
+
+    /// Expected placements were recorded from the tree before it moved to an arena.
+    #[test]
+    fn placements_match_the_pointer_based_tree() {
+        let mut tree = SpatialTree::new();
+        assert_eq!(placements(&mut tree, &sizes(16, 1)), vec![
+            Some((0, 0, 15, 4)), Some((0, 4, 7, 1)), Some((0, 5, 25, 6)), Some((0, 11, 21, 23)),
+            Some((25, 0, 10, 17)), Some((21, 11, 4, 23)), Some((0, 34, 13, 1)),
+            Some((35, 0, 15, 23)), Some((35, 23, 11, 6)), Some((0, 35, 23, 16)),
+            Some((25, 17, 8, 8)), Some((23, 35, 27, 1)), Some((25, 25, 10, 9)),
+            Some((23, 36, 25, 3)), Some((50, 0, 22, 29)), Some((0, 51, 18, 29)),
+        ]);
+        assert_eq!(*tree.region(), Region::new(0, 0, 72, 80));
+
+        let mut tree = SpatialTree::new();
+        tree.set_allow_rotation(true);
+        assert_eq!(placements(&mut tree, &sizes(16, 2)), vec![
+            Some((0, 0, 11, 13)), Some((11, 0, 7, 15)), Some((0, 15, 30, 6)),
+            Some((0, 21, 19, 19)), Some((19, 21, 3, 16)), Some((0, 40, 24, 3)),
+            Some((18, 0, 5, 5)), Some((0, 43, 25, 7)), Some((30, 0, 24, 25)),
+            Some((0, 50, 27, 26)), Some((30, 25, 9, 3)), Some((30, 28, 15, 15)),
+            Some((27, 50, 7, 26)), Some((54, 0, 13, 30)), Some((54, 30, 8, 24)),
+            Some((34, 50, 19, 23)),
+        ]);
+        assert_eq!(*tree.region(), Region::new(0, 0, 67, 76));
+
+        let mut tree = SpatialTree::with_max_size(64, 64);
+        assert_eq!(placements(&mut tree, &sizes(16, 3)), vec![
+            Some((0, 0, 30, 14)), Some((30, 0, 6, 29)), Some((0, 29, 25, 24)),
+            Some((0, 14, 26, 6)), Some((0, 53, 30, 10)), Some((36, 0, 14, 13)), None,
+            Some((0, 20, 29, 6)), Some((36, 13, 10, 20)), Some((36, 33, 8, 27)),
+            Some((44, 33, 6, 17)), Some((50, 0, 12, 7)), Some((50, 7, 12, 12)), None, None, None,
+        ]);
+        assert_eq!(*tree.region(), Region::new(0, 0, 62, 63));
+    }
+
+    /// A tree with some items removed again, so queries also have to skip freed space.
+    fn churned_tree() -> SpatialTree<usize> {
+        let mut tree = SpatialTree::new();
+        for (i, &(width, height)) in sizes(40, 5).iter().enumerate() {
+            tree.insert(i, width, height).unwrap();
+        }
+        for i in (0..40).step_by(4) {
+            tree.remove(&i);
+        }
+        tree
+    }
+
+    #[test]
+    fn queries_match_a_linear_scan() {
+        let tree = churned_tree();
+        let placements = tree.iter().map(|(&item, region)| (item, region)).collect::<Vec<_>>();
+        let region = tree.region().clone();
+        for y in 0..region.height + 2 {
+            for x in 0..region.width + 2 {
+                let expected = placements.iter().find(|(_, region)| region.contains_point(x, y));
+                let found = tree.item_at(x, y).map(|(&item, region)| (item, region));
+                assert_eq!(found.as_ref(), expected, "at {}, {}", x, y);
+            }
+        }
+        for (top, left, width, height) in [(0, 0, 1, 1), (5, 7, 20, 3), (10, 30, 1, 40),
+            (0, 0, region.width, region.height), (region.height, 0, 10, 10)]
+        {
+            let query = Region::new(top, left, width, height);
+            let mut found = tree.items_intersecting(&query).into_iter()
+                .map(|(&item, region)| (item, region))
+                .collect::<Vec<_>>();
+            found.sort_by_key(|&(item, _)| item);
+            let mut expected = placements.iter()
+                .filter(|(_, region)| region.intersects(&query))
+                .cloned()
+                .collect::<Vec<_>>();
+            expected.sort_by_key(|&(item, _)| item);
+            assert_eq!(found, expected, "in {:?}", query);
+        }
+    }
+
+    #[test]
+    fn iterators_report_exact_lengths() {
+        let mut tree = churned_tree();
+        let nodes = tree.iter_nodes().count();
+        assert_eq!(tree.size(), 30);
+        assert_eq!(tree.iter().len(), 30);
+        assert_eq!(tree.iter_mut().len(), 30);
+        assert_eq!(tree.iter_nodes().len(), nodes);
+        assert_eq!(tree.iter_nodes_mut().len(), nodes);
+
+        let mut iter = tree.iter();
+        for remaining in (0..30).rev() {
+            assert!(iter.next().is_some());
+            assert_eq!(iter.len(), remaining);
+        }
+        assert!(iter.next().is_none());
+        let mut iter = tree.iter_nodes_mut();
+        iter.next();
+        assert_eq!(iter.len(), nodes - 1);
+    }
+
+    #[test]
+    fn serde_round_trip_keeps_packing_the_same_way() {
+        let mut tree = churned_tree();
+        let mut parsed = serde_json::from_str::<SpatialTree<usize>>(
+            &serde_json::to_string(&tree).unwrap()).unwrap();
+        assert_eq!(parsed, tree);
+        for (i, &(width, height)) in sizes(20, 6).iter().enumerate() {
+            assert_eq!(parsed.insert(100 + i, width, height), tree.insert(100 + i, width, height));
+        }
+        assert_eq!(parsed, tree);
+    }
+
+    #[test]
+    fn removed_space_is_reused() {
+        for seed in 0..50 {
+            let sizes = sizes(40, seed);
+            let mut tree = SpatialTree::new();
+            tree.set_allow_rotation(seed % 2 == 0);
+            for (i, &(width, height)) in sizes.iter().enumerate() {
+                tree.insert(i, width, height).unwrap();
+            }
+            let region = tree.region().clone();
+            for round in 0..40 {
+                let item = (seed as usize * 7 + round * 13) % sizes.len();
+                assert_eq!(tree.remove(&item), Some(item));
+                let (width, height) = sizes[item];
+                tree.insert(item, width, height).unwrap();
+                assert_eq!(*tree.region(), region, "seed {} grew after reinserting {}", seed, item);
+            }
+            assert_disjoint(&tree);
+        }
+    }
+
+    #[test]
+    fn removing_everything_leaves_one_free_leaf() {
+        let mut tree = SpatialTree::new();
+        for (i, &(width, height)) in sizes(30, 7).iter().enumerate() {
+            tree.insert(i, width, height).unwrap();
+        }
+        for i in 0..30 {
+            assert_eq!(tree.remove(&i), Some(i));
+        }
+        assert_eq!(tree.remove(&0), None);
+        assert_eq!(tree.size(), 0);
+        assert_eq!(tree.iter_nodes().count(), 1);
+        assert!(tree.root().unwrap().is_free());
+        let region = tree.region().clone();
+        assert!(tree.insert(0, region.width, region.height).is_ok());
+        assert_eq!(*tree.region(), region);
+    }
+
+    #[test]
+    fn corrupt_layouts_are_rejected() {
+        let mut tree = SpatialTree::new();
+        for (i, &(width, height)) in sizes(20, 3).iter().enumerate() {
+            tree.insert(i, width, height).unwrap();
+        }
+        for i in (0..20).step_by(3) {
+            tree.remove(&i);
+        }
+        let json = serde_json::to_value(&tree).unwrap();
+        let parsed = serde_json::from_value::<SpatialTree<usize>>(json.clone()).unwrap();
+        assert_eq!(parsed, tree);
+
+        let root = json["root"].as_u64().unwrap() as usize;
+        let child = tree.root().unwrap().right.unwrap().0;
+        let rejects = |corrupt: &dyn Fn(&mut serde_json::Value)| {
+            let mut json = json.clone();
+            corrupt(&mut json);
+            serde_json::from_value::<SpatialTree<usize>>(json).is_err()
+        };
+        assert!(rejects(&|json| json["root"] = 999.into()));
+        assert!(rejects(&|json| json["nodes"][child]["right"] = root.into()));
+        assert!(rejects(&|json| json["nodes"][child]["parent"] = child.into()));
+        assert!(rejects(&|json| json["num_items"] = 100.into()));
+        assert!(rejects(&|json| json["vacant"] = vec![root].into()));
+        assert!(rejects(&|json| json["nodes"][root]["value_size"] = serde_json::json!([u32::MAX, 1])));
+    }
+}